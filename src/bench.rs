@@ -0,0 +1,46 @@
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, seq::IndexedRandom};
+
+use crate::{card::Shoe, game::State, rules::Rules};
+
+/// Prints playouts per second from the start of the game, first sampling from the full move list
+/// at every step as the search used to, then through `State::random_move`.
+pub fn playouts(rules: &Rules, shoe: &Shoe, duration: Duration) {
+    let mut rng = rand::rng();
+
+    let listed = rate(duration, || listed_playout(rules, shoe, &mut rng));
+    println!("Move list playouts:   {:>12.0}/s", listed);
+
+    let direct = rate(duration, || State::Start.playout(rules, shoe, &mut rng));
+    println!("Random move playouts: {:>12.0}/s", direct);
+
+    println!("Speed-up: {:.2}x", direct / listed);
+}
+
+fn listed_playout<R: Rng>(rules: &Rules, shoe: &Shoe, rng: &mut R) -> u32 {
+    let mut game = State::Start;
+    loop {
+        if let State::Finished(x) = game {
+            return x;
+        }
+        let moves = game.get_weighted_moves(rules, shoe);
+        let (mov, _) = moves.choose_weighted(rng, |(_, weight)| *weight).unwrap();
+        game = game.apply_move(*mov, rules).unwrap();
+    }
+}
+
+fn rate(duration: Duration, mut playout: impl FnMut() -> u32) -> f64 {
+    let start = Instant::now();
+    let mut count = 0u64;
+    while start.elapsed() < duration {
+        for _ in 0..1000 {
+            black_box(playout());
+        }
+        count += 1000;
+    }
+    count as f64 / start.elapsed().as_secs_f64()
+}
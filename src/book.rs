@@ -0,0 +1,100 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    card::{Shoe, SuitMap},
+    game::{Move, State},
+    rules::Rules,
+    snapshot,
+    solver::Solver,
+};
+
+/// Exact move values for every decision from the start of a round up to the stage 4 guess, solved
+/// ahead of time to warm-start the search, so its first recommendations are not noise. A book only
+/// applies to the rules and shoe it was generated for.
+#[derive(Serialize, Deserialize)]
+pub struct Book {
+    rules: Rules,
+    shoe: Shoe,
+    /// Canonical player states with the value of each of their moves.
+    entries: Vec<(State, Vec<(Move, f64)>)>,
+    #[serde(skip)]
+    index: HashMap<State, usize>,
+    #[serde(skip)]
+    symmetries: Vec<SuitMap>,
+}
+impl Book {
+    pub fn generate(rules: Rules, shoe: Shoe) -> Book {
+        let mut book = Book {
+            rules,
+            symmetries: shoe.symmetries(),
+            shoe: shoe.clone(),
+            entries: vec![],
+            index: HashMap::new(),
+        };
+        let mut solver = Solver::new(rules, shoe);
+        let mut seen = HashSet::new();
+        let mut stack = vec![State::Start];
+        while let Some(state) = stack.pop() {
+            let (state, _) = state.canonical(&book.symmetries);
+            if state.is_terminal() || !seen.insert(state) {
+                continue;
+            }
+            if !state.is_dealer_turn() {
+                book.index.insert(state, book.entries.len());
+                book.entries.push((state, solver.move_values(state)));
+                if matches!(state, State::Stage3DealerPicked(..)) {
+                    continue;
+                }
+            }
+            stack.extend(
+                state
+                    .get_valid_moves(&book.rules, &book.shoe)
+                    .into_iter()
+                    .filter_map(|mov| state.apply_move(mov, &book.rules)),
+            );
+        }
+        book
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        snapshot::write(path, self)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Book> {
+        let mut book: Book = snapshot::read(path)?;
+        book.index = book
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, (state, _))| (*state, i))
+            .collect();
+        book.symmetries = book.shoe.symmetries();
+        Ok(book)
+    }
+
+    /// The value of each move from `state`, if the book covers it under these rules and shoe.
+    pub fn moves(&self, state: State, rules: &Rules, shoe: &Shoe) -> Option<Vec<(Move, f64)>> {
+        if self.rules != *rules || self.shoe != *shoe {
+            return None;
+        }
+        let (canonical, map) = state.canonical(&self.symmetries);
+        let to_real = map.inverse();
+        let (_, moves) = &self.entries[*self.index.get(&canonical)?];
+        Some(
+            moves
+                .iter()
+                .map(|(mov, value)| (mov.map_suits(&to_real), *value))
+                .collect(),
+        )
+    }
+}
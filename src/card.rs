@@ -1,6 +1,9 @@
-use std::fmt::Display;
+use std::fmt::{self, Display};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Suit {
     Hearts,
     Diamonds,
@@ -8,6 +11,8 @@ pub enum Suit {
     Spades,
 }
 impl Suit {
+    pub const ALL: [Suit; 4] = [Suit::Hearts, Suit::Diamonds, Suit::Clubs, Suit::Spades];
+
     pub fn colour(&self) -> Colour {
         match self {
             Suit::Hearts => Colour::Red,
@@ -28,7 +33,7 @@ impl Display for Suit {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Colour {
     Red,
     Black,
@@ -42,7 +47,7 @@ impl Display for Colour {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Value {
     Two,
     Three,
@@ -58,6 +63,23 @@ pub enum Value {
     King,
     Ace,
 }
+impl Value {
+    pub const ALL: [Value; 13] = [
+        Value::Two,
+        Value::Three,
+        Value::Four,
+        Value::Five,
+        Value::Six,
+        Value::Seven,
+        Value::Eight,
+        Value::Nine,
+        Value::Ten,
+        Value::Jack,
+        Value::Queen,
+        Value::King,
+        Value::Ace,
+    ];
+}
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -78,80 +100,260 @@ impl Display for Value {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Card {
-    pub suit: Suit,
-    pub value: Value,
-}
+/// A card packed into its index in a suit-major deck, `suit * 13 + value`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Card(u8);
 impl Card {
     pub const fn new(suit: Suit, value: Value) -> Self {
-        Card { suit, value }
+        Card(suit as u8 * 13 + value as u8)
     }
 
-    pub fn rest_of_deck(cards: &[Self]) -> Vec<Self> {
-        DECK.iter()
-            .filter(|card| !cards.contains(card))
-            .copied()
-            .collect()
+    pub fn suit(&self) -> Suit {
+        Suit::ALL[(self.0 / 13) as usize]
+    }
+
+    pub fn value(&self) -> Value {
+        Value::ALL[(self.0 % 13) as usize]
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+impl fmt::Debug for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Card")
+            .field("suit", &self.suit())
+            .field("value", &self.value())
+            .finish()
     }
 }
 impl Display for Card {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} of {}", self.value, self.suit)
-    }
-}
-
-static DECK: [Card; 52] = [
-    Card::new(Suit::Hearts, Value::Two),
-    Card::new(Suit::Hearts, Value::Three),
-    Card::new(Suit::Hearts, Value::Four),
-    Card::new(Suit::Hearts, Value::Five),
-    Card::new(Suit::Hearts, Value::Six),
-    Card::new(Suit::Hearts, Value::Seven),
-    Card::new(Suit::Hearts, Value::Eight),
-    Card::new(Suit::Hearts, Value::Nine),
-    Card::new(Suit::Hearts, Value::Ten),
-    Card::new(Suit::Hearts, Value::Jack),
-    Card::new(Suit::Hearts, Value::Queen),
-    Card::new(Suit::Hearts, Value::King),
-    Card::new(Suit::Hearts, Value::Ace),
-    Card::new(Suit::Diamonds, Value::Two),
-    Card::new(Suit::Diamonds, Value::Three),
-    Card::new(Suit::Diamonds, Value::Four),
-    Card::new(Suit::Diamonds, Value::Five),
-    Card::new(Suit::Diamonds, Value::Six),
-    Card::new(Suit::Diamonds, Value::Seven),
-    Card::new(Suit::Diamonds, Value::Eight),
-    Card::new(Suit::Diamonds, Value::Nine),
-    Card::new(Suit::Diamonds, Value::Ten),
-    Card::new(Suit::Diamonds, Value::Jack),
-    Card::new(Suit::Diamonds, Value::Queen),
-    Card::new(Suit::Diamonds, Value::King),
-    Card::new(Suit::Diamonds, Value::Ace),
-    Card::new(Suit::Clubs, Value::Two),
-    Card::new(Suit::Clubs, Value::Three),
-    Card::new(Suit::Clubs, Value::Four),
-    Card::new(Suit::Clubs, Value::Five),
-    Card::new(Suit::Clubs, Value::Six),
-    Card::new(Suit::Clubs, Value::Seven),
-    Card::new(Suit::Clubs, Value::Eight),
-    Card::new(Suit::Clubs, Value::Nine),
-    Card::new(Suit::Clubs, Value::Ten),
-    Card::new(Suit::Clubs, Value::Jack),
-    Card::new(Suit::Clubs, Value::Queen),
-    Card::new(Suit::Clubs, Value::King),
-    Card::new(Suit::Clubs, Value::Ace),
-    Card::new(Suit::Spades, Value::Two),
-    Card::new(Suit::Spades, Value::Three),
-    Card::new(Suit::Spades, Value::Four),
-    Card::new(Suit::Spades, Value::Five),
-    Card::new(Suit::Spades, Value::Six),
-    Card::new(Suit::Spades, Value::Seven),
-    Card::new(Suit::Spades, Value::Eight),
-    Card::new(Suit::Spades, Value::Nine),
-    Card::new(Suit::Spades, Value::Ten),
-    Card::new(Suit::Spades, Value::Jack),
-    Card::new(Suit::Spades, Value::Queen),
-    Card::new(Suit::Spades, Value::King),
-    Card::new(Suit::Spades, Value::Ace),
-];
+        write!(f, "{} of {}", self.value(), self.suit())
+    }
+}
+
+/// A set of distinct cards as one bit per card index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct DeckSet(u64);
+impl DeckSet {
+    pub const FULL: DeckSet = DeckSet((1 << 52) - 1);
+
+    pub fn remove(&mut self, card: Card) {
+        self.0 &= !(1 << card.0);
+    }
+
+    pub fn len(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Card> {
+        let mut bits = self.0;
+        std::iter::from_fn(move || {
+            if bits == 0 {
+                return None;
+            }
+            let index = bits.trailing_zeros() as u8;
+            bits &= bits - 1;
+            Some(Card(index))
+        })
+    }
+
+    /// Picks a card uniformly from the set.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Option<Card> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut bits = self.0;
+        for _ in 0..rng.random_range(0..self.len()) {
+            bits &= bits - 1;
+        }
+        Some(Card(bits.trailing_zeros() as u8))
+    }
+}
+
+/// The cards left to deal from, counted per card so a shoe can hold several decks. Saved as just
+/// the counts, since the rest follows from them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "Vec<u8>", try_from = "Vec<u8>")]
+pub struct Shoe {
+    counts: [u8; 52],
+    /// Every card with at least one copy left.
+    cards: DeckSet,
+    /// Whether no card has more than one copy, so `cards` alone describes the shoe.
+    single: bool,
+}
+impl Shoe {
+    pub fn new(decks: u8) -> Self {
+        Shoe {
+            counts: [decks; 52],
+            cards: DeckSet::FULL,
+            single: decks == 1,
+        }
+    }
+
+    pub fn count(&self, card: Card) -> u32 {
+        self.counts[card.index()] as u32
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.iter().map(|count| *count as u32).sum()
+    }
+
+    /// Takes one copy of `card` out of the shoe once it has been dealt.
+    pub fn remove(&mut self, card: Card) {
+        let count = &mut self.counts[card.index()];
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            self.cards.remove(card);
+        }
+        self.single = self.counts.iter().all(|count| *count <= 1);
+    }
+
+    /// Every card still available once `seen` have been dealt from the shoe, along with how many
+    /// copies of it are left.
+    pub fn remaining(&self, seen: &[Card]) -> Vec<(Card, u32)> {
+        self.cards
+            .iter()
+            .filter_map(|card| {
+                let dealt = seen.iter().filter(|c| **c == card).count() as u32;
+                let count = self.count(card).saturating_sub(dealt);
+                (count > 0).then_some((card, count))
+            })
+            .collect()
+    }
+
+    /// Draws a random card once `seen` have been dealt, weighted by the copies left.
+    pub fn draw<R: Rng>(&self, seen: &[Card], rng: &mut R) -> Card {
+        if self.single {
+            let mut cards = self.cards;
+            for card in seen {
+                cards.remove(*card);
+            }
+            return cards.sample(rng).unwrap();
+        }
+
+        // Draw from the whole shoe and reject copies that are already on the table.
+        loop {
+            let card = self.sample(rng);
+            let dealt = seen.iter().filter(|c| **c == card).count() as u32;
+            if rng.random_range(0..self.count(card)) >= dealt {
+                return card;
+            }
+        }
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> Card {
+        let mut target = rng.random_range(0..self.total());
+        for card in self.cards.iter() {
+            let count = self.count(card);
+            if target < count {
+                return card;
+            }
+            target -= count;
+        }
+        unreachable!()
+    }
+
+    /// The suit relabellings that leave the shoe unchanged. Only these keep positions equivalent
+    /// once cards from earlier rounds are missing.
+    pub fn symmetries(&self) -> Vec<SuitMap> {
+        SuitMap::all()
+            .filter(|map| {
+                DeckSet::FULL
+                    .iter()
+                    .all(|card| self.count(map.card(card)) == self.count(card))
+            })
+            .collect()
+    }
+}
+
+impl From<Shoe> for Vec<u8> {
+    fn from(shoe: Shoe) -> Self {
+        shoe.counts.to_vec()
+    }
+}
+impl TryFrom<Vec<u8>> for Shoe {
+    type Error = &'static str;
+
+    fn try_from(counts: Vec<u8>) -> Result<Self, Self::Error> {
+        let counts: [u8; 52] = counts
+            .try_into()
+            .map_err(|_| "A shoe needs a count for each of the 52 cards")?;
+        let mut cards = DeckSet::FULL;
+        for card in DeckSet::FULL.iter() {
+            if counts[card.index()] == 0 {
+                cards.remove(card);
+            }
+        }
+        Ok(Shoe {
+            counts,
+            cards,
+            single: counts.iter().all(|count| *count <= 1),
+        })
+    }
+}
+
+/// A relabelling of suits that keeps same-coloured suits together, so the colour of every card is
+/// relabelled consistently. Positions that differ only by such a relabelling are equivalent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SuitMap([Suit; 4]);
+impl SuitMap {
+    pub const IDENTITY: SuitMap = SuitMap(Suit::ALL);
+
+    pub fn all() -> impl Iterator<Item = SuitMap> {
+        (0..8).map(|i| {
+            let (red, black) = if i & 1 == 0 {
+                ([Suit::Hearts, Suit::Diamonds], [Suit::Clubs, Suit::Spades])
+            } else {
+                ([Suit::Clubs, Suit::Spades], [Suit::Hearts, Suit::Diamonds])
+            };
+            let (hearts, diamonds) = if i & 2 == 0 {
+                (red[0], red[1])
+            } else {
+                (red[1], red[0])
+            };
+            let (clubs, spades) = if i & 4 == 0 {
+                (black[0], black[1])
+            } else {
+                (black[1], black[0])
+            };
+            SuitMap([hearts, diamonds, clubs, spades])
+        })
+    }
+
+    pub fn suit(&self, suit: Suit) -> Suit {
+        self.0[suit as usize]
+    }
+
+    pub fn colour(&self, colour: Colour) -> Colour {
+        let swapped = self.suit(Suit::Hearts).colour() != Colour::Red;
+        match (colour, swapped) {
+            (colour, false) => colour,
+            (Colour::Red, true) => Colour::Black,
+            (Colour::Black, true) => Colour::Red,
+        }
+    }
+
+    pub fn card(&self, card: Card) -> Card {
+        Card::new(self.suit(card.suit()), card.value())
+    }
+
+    /// The map that applies `self` and then `other`.
+    pub fn then(&self, other: &SuitMap) -> SuitMap {
+        SuitMap(self.0.map(|suit| other.suit(suit)))
+    }
+
+    pub fn inverse(&self) -> SuitMap {
+        let mut suits = Suit::ALL;
+        for suit in Suit::ALL {
+            suits[self.suit(suit) as usize] = suit;
+        }
+        SuitMap(suits)
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    card::Shoe,
+    game::{Move, State},
+    rules::Rules,
+    search::{self, Budget, SearchConfig},
+    solver::Solver,
+};
+
+/// Positions with a real decision at each stage, as the moves that reach them from the start.
+const POSITIONS: [&[&str]; 6] = [
+    &[],
+    &["red", "five of hearts"],
+    &["black", "king of spades"],
+    &["red", "five of hearts", "higher", "nine of clubs"],
+    &["red", "nine of hearts", "lower", "eight of spades"],
+    &[
+        "red",
+        "five of hearts",
+        "higher",
+        "nine of clubs",
+        "inside",
+        "seven of diamonds",
+    ],
+];
+
+/// How far the search's value of a position may stray from the exact value, as a fraction of it.
+/// Nodes the tree has barely visited are valued by random playouts, so estimates approach the
+/// exact value from below as the tree grows rather than settling within their interval.
+const TOLERANCE: f64 = 0.1;
+
+/// Searches each known position and compares the most visited move with the exact solver. A
+/// position passes when that move is optimal up to the search's 95% interval, so near ties are not
+/// failed on noise, and when its backed-up value is within `TOLERANCE` of the exact game value.
+/// A search that tried no move, or visited its move too rarely to have an interval, fails.
+/// Returns whether every position passed.
+pub fn against_solver(rules: Rules, shoe: &Shoe, budget: Budget, config: SearchConfig) -> bool {
+    let mut solver = Solver::new(rules, shoe.clone());
+    let mut passed = 0;
+
+    for moves in POSITIONS {
+        let state = moves.iter().fold(State::Start, |state, mov| {
+            state
+                .apply_move(mov.parse::<Move>().unwrap(), &rules)
+                .unwrap()
+        });
+        let exact = solver.move_values(state);
+        let optimum = exact
+            .iter()
+            .map(|(_, value)| *value)
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let name = if moves.is_empty() {
+            "start".to_string()
+        } else {
+            moves.join(", ")
+        };
+        let result = search::search(state, rules, shoe, budget, config);
+        let Some(best) = result.best() else {
+            println!("{:<8} {}", "FAILED", name);
+            println!(
+                "         search tried no move after {} iterations",
+                result.iterations
+            );
+            continue;
+        };
+        let (_, value) = exact.iter().find(|(mov, _)| *mov == best.mov).unwrap();
+        let (lower, upper) = best.interval();
+        let tolerance = rules.denormalise(upper - lower) / 2.0;
+        let estimate = rules.denormalise(best.value);
+        let ok = tolerance.is_finite()
+            && optimum - value <= tolerance
+            && (optimum - estimate).abs() <= tolerance + TOLERANCE * optimum;
+        if ok {
+            passed += 1;
+        }
+
+        println!("{:<8} {}", if ok { "ok" } else { "FAILED" }, name);
+        println!(
+            "         search picked {} at x{:.3} (± {:.3}) after {} iterations, exact x{:.3}, \
+             optimum x{:.3}",
+            best.mov, estimate, tolerance, result.iterations, value, optimum
+        );
+    }
+
+    println!("{}/{} positions passed", passed, POSITIONS.len());
+    passed == POSITIONS.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_converges_to_solver() {
+        let rules = Rules::default();
+        let config = SearchConfig {
+            seed: Some(1),
+            ..SearchConfig::default()
+        };
+        assert!(against_solver(
+            rules,
+            &Shoe::new(rules.decks),
+            Budget::Iterations(500_000),
+            config
+        ));
+    }
+}
@@ -1,20 +1,44 @@
 use std::{fmt, str::FromStr};
 
 use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
 
-use crate::card;
+use crate::{
+    card,
+    rules::{Rules, TiePolicy},
+};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// The result of comparing the dealer's card against a guess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Win,
+    Lose,
+    Push,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum HiLo {
     Higher,
     Lower,
+    /// Only reachable through `Move::Same` when the rules allow it.
+    Same,
 }
 impl HiLo {
-    fn is_true(&self, card0: &card::Card, card1: &card::Card) -> bool {
-        match self {
-            Self::Higher => card0.value > card1.value,
-            Self::Lower => card0.value < card1.value,
+    fn outcome(&self, card0: &card::Card, card1: &card::Card, ties: TiePolicy) -> Outcome {
+        if card0.value() == card1.value() {
+            return match (self, ties) {
+                (Self::Same, _) => Outcome::Win,
+                (_, TiePolicy::Push) => Outcome::Push,
+                _ => Outcome::Lose,
+            };
         }
+
+        let won = match self {
+            Self::Higher => card0.value() > card1.value(),
+            Self::Lower => card0.value() < card1.value(),
+            Self::Same => false,
+        };
+        if won { Outcome::Win } else { Outcome::Lose }
     }
 }
 impl fmt::Display for HiLo {
@@ -22,24 +46,44 @@ impl fmt::Display for HiLo {
         match self {
             Self::Higher => write!(f, "Higher"),
             Self::Lower => write!(f, "Lower"),
+            Self::Same => write!(f, "Same"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum InOut {
     Inside,
     Outside,
+    /// Only reachable through `Move::Same` when the rules allow it.
+    Same,
 }
 impl InOut {
-    fn is_true(&self, card0: &card::Card, card1: &card::Card, card2: &card::Card) -> bool {
-        let small = Ord::min(card0.value, card2.value);
-        let large = Ord::max(card0.value, card2.value);
+    fn outcome(
+        &self,
+        card0: &card::Card,
+        card1: &card::Card,
+        card2: &card::Card,
+        ties: TiePolicy,
+    ) -> Outcome {
+        let small = Ord::min(card0.value(), card2.value());
+        let large = Ord::max(card0.value(), card2.value());
 
-        match self {
-            Self::Inside => small < card1.value && card1.value < large,
-            Self::Outside => card1.value < small || large < card1.value,
+        if card1.value() == small || card1.value() == large {
+            return match (self, ties) {
+                (Self::Same, _) => Outcome::Win,
+                (_, TiePolicy::Push) => Outcome::Push,
+                (Self::Outside, TiePolicy::OutsideWins) => Outcome::Win,
+                _ => Outcome::Lose,
+            };
         }
+
+        let won = match self {
+            Self::Inside => small < card1.value() && card1.value() < large,
+            Self::Outside => card1.value() < small || large < card1.value(),
+            Self::Same => false,
+        };
+        if won { Outcome::Win } else { Outcome::Lose }
     }
 }
 impl fmt::Display for InOut {
@@ -47,11 +91,12 @@ impl fmt::Display for InOut {
         match self {
             Self::Inside => write!(f, "Inside"),
             Self::Outside => write!(f, "Outside"),
+            Self::Same => write!(f, "Same"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum State {
     Start,
     Stage1PlayerPicked(card::Colour),
@@ -64,7 +109,7 @@ pub enum State {
     Finished(u32),
 }
 impl State {
-    pub fn apply_move(&self, mov: Move) -> Option<Self> {
+    pub fn apply_move(&self, mov: Move, rules: &Rules) -> Option<Self> {
         match self {
             State::Start => {
                 if let Move::Colour(colour) = mov {
@@ -75,7 +120,7 @@ impl State {
             }
             State::Stage1PlayerPicked(colour) => {
                 if let Move::Card(card) = mov {
-                    if colour != &card.suit.colour() {
+                    if colour != &card.suit().colour() {
                         Some(State::Finished(0))
                     } else {
                         Some(State::Stage1DealerPicked(*colour, card))
@@ -84,41 +129,49 @@ impl State {
                     None
                 }
             }
-            State::Stage1DealerPicked(_, card) => {
-                if let Move::HiLo(hi_lo) = mov {
-                    Some(State::Stage2PlayerPicked(*card, hi_lo))
-                } else if let Move::Finish = mov {
-                    Some(State::Finished(2))
-                } else {
-                    None
+            State::Stage1DealerPicked(_, card) => match mov {
+                Move::HiLo(HiLo::Same) => None,
+                Move::HiLo(hi_lo) => Some(State::Stage2PlayerPicked(*card, hi_lo)),
+                Move::Same if rules.ties == TiePolicy::Same => {
+                    Some(State::Stage2PlayerPicked(*card, HiLo::Same))
                 }
-            }
+                Move::Finish => Some(State::Finished(rules.payouts.stage1)),
+                _ => None,
+            },
             State::Stage2PlayerPicked(card, hi_lo) => {
                 if let Move::Card(card1) = mov {
-                    if !hi_lo.is_true(&card1, card) {
-                        Some(State::Finished(0))
-                    } else {
-                        Some(State::Stage2DealerPicked(*card, *hi_lo, card1))
+                    match hi_lo.outcome(&card1, card, rules.ties) {
+                        Outcome::Lose => Some(State::Finished(0)),
+                        Outcome::Push => Some(State::Finished(rules.payouts.stage1)),
+                        Outcome::Win if *hi_lo == HiLo::Same => {
+                            Some(State::Finished(rules.payouts.same))
+                        }
+                        Outcome::Win => Some(State::Stage2DealerPicked(*card, *hi_lo, card1)),
                     }
                 } else {
                     None
                 }
             }
-            State::Stage2DealerPicked(card, _, card1) => {
-                if let Move::InOut(in_out) = mov {
-                    Some(State::Stage3PlayerPicked(*card, *card1, in_out))
-                } else if let Move::Finish = mov {
-                    Some(State::Finished(3))
-                } else {
-                    None
+            State::Stage2DealerPicked(card, _, card1) => match mov {
+                Move::InOut(InOut::Same) => None,
+                Move::InOut(in_out) => Some(State::Stage3PlayerPicked(*card, *card1, in_out)),
+                Move::Same if rules.ties == TiePolicy::Same => {
+                    Some(State::Stage3PlayerPicked(*card, *card1, InOut::Same))
                 }
-            }
+                Move::Finish => Some(State::Finished(rules.payouts.stage2)),
+                _ => None,
+            },
             State::Stage3PlayerPicked(card, card1, in_out) => {
                 if let Move::Card(card2) = mov {
-                    if !in_out.is_true(card, &card2, card1) {
-                        Some(State::Finished(0))
-                    } else {
-                        Some(State::Stage3DealerPicked(*card, *card1, *in_out, card2))
+                    match in_out.outcome(card, &card2, card1, rules.ties) {
+                        Outcome::Lose => Some(State::Finished(0)),
+                        Outcome::Push => Some(State::Finished(rules.payouts.stage2)),
+                        Outcome::Win if *in_out == InOut::Same => {
+                            Some(State::Finished(rules.payouts.same))
+                        }
+                        Outcome::Win => {
+                            Some(State::Stage3DealerPicked(*card, *card1, *in_out, card2))
+                        }
                     }
                 } else {
                     None
@@ -128,17 +181,17 @@ impl State {
                 if let Move::Suit(suit) = mov {
                     Some(State::Stage4PlayerPicked(*card, *card1, *card2, suit))
                 } else if let Move::Finish = mov {
-                    Some(State::Finished(4))
+                    Some(State::Finished(rules.payouts.stage3))
                 } else {
                     None
                 }
             }
             State::Stage4PlayerPicked(_, _, _, suit) => {
                 if let Move::Card(card3) = mov {
-                    if suit != &card3.suit {
+                    if suit != &card3.suit() {
                         Some(State::Finished(0))
                     } else {
-                        Some(State::Finished(20))
+                        Some(State::Finished(rules.payouts.stage4))
                     }
                 } else {
                     None
@@ -147,70 +200,160 @@ impl State {
             State::Finished(_) => None,
         }
     }
-    pub fn get_valid_moves(&self) -> Vec<Move> {
+    pub fn get_valid_moves(&self, rules: &Rules, shoe: &card::Shoe) -> Vec<Move> {
+        self.get_weighted_moves(rules, shoe)
+            .into_iter()
+            .map(|(mov, _)| mov)
+            .collect()
+    }
+
+    /// Every valid move along with how many ways it can come about. The dealer's cards are
+    /// weighted by the copies left in the shoe, the player's choices all count once.
+    pub fn get_weighted_moves(&self, rules: &Rules, shoe: &card::Shoe) -> Vec<(Move, u32)> {
+        let dealer_moves = |seen: &[card::Card]| {
+            shoe.remaining(seen)
+                .into_iter()
+                .map(|(card, count)| (Move::Card(card), count))
+                .collect()
+        };
+        let player_moves = |moves: &[Move], can_tie: bool| {
+            let mut moves: Vec<(Move, u32)> = moves.iter().map(|mov| (*mov, 1)).collect();
+            if can_tie && rules.ties == TiePolicy::Same {
+                moves.push((Move::Same, 1));
+            }
+            moves.push((Move::Finish, 1));
+            moves
+        };
+
         match self {
             State::Start => vec![
-                Move::Colour(card::Colour::Red),
-                Move::Colour(card::Colour::Black),
-            ],
-            State::Stage1PlayerPicked(_) => card::Card::rest_of_deck(&[])
-                .into_iter()
-                .map(|c| Move::Card(c))
-                .collect(),
-            State::Stage1DealerPicked(_, _) => vec![
-                Move::HiLo(HiLo::Higher),
-                Move::HiLo(HiLo::Lower),
-                Move::Finish,
-            ],
-            State::Stage2PlayerPicked(card, _) => card::Card::rest_of_deck(&[*card])
-                .into_iter()
-                .map(|c| Move::Card(c))
-                .collect(),
-            State::Stage2DealerPicked(_, _, _) => vec![
-                Move::InOut(InOut::Inside),
-                Move::InOut(InOut::Outside),
-                Move::Finish,
-            ],
-            State::Stage3PlayerPicked(card, card1, _) => card::Card::rest_of_deck(&[*card, *card1])
-                .into_iter()
-                .map(|c| Move::Card(c))
-                .collect(),
-            State::Stage3DealerPicked(_, _, _, _) => vec![
-                Move::Suit(card::Suit::Hearts),
-                Move::Suit(card::Suit::Diamonds),
-                Move::Suit(card::Suit::Clubs),
-                Move::Suit(card::Suit::Spades),
-                Move::Finish,
+                (Move::Colour(card::Colour::Red), 1),
+                (Move::Colour(card::Colour::Black), 1),
             ],
+            State::Stage1PlayerPicked(_) => dealer_moves(&[]),
+            State::Stage1DealerPicked(_, _) => {
+                player_moves(&[Move::HiLo(HiLo::Higher), Move::HiLo(HiLo::Lower)], true)
+            }
+            State::Stage2PlayerPicked(card, _) => dealer_moves(&[*card]),
+            State::Stage2DealerPicked(_, _, _) => player_moves(
+                &[Move::InOut(InOut::Inside), Move::InOut(InOut::Outside)],
+                true,
+            ),
+            State::Stage3PlayerPicked(card, card1, _) => dealer_moves(&[*card, *card1]),
+            State::Stage3DealerPicked(_, _, _, _) => {
+                player_moves(&card::Suit::ALL.map(Move::Suit), false)
+            }
             State::Stage4PlayerPicked(card, card1, card2, _) => {
-                card::Card::rest_of_deck(&[*card, *card1, *card2])
-                    .into_iter()
-                    .map(|c| Move::Card(c))
-                    .collect()
+                dealer_moves(&[*card, *card1, *card2])
             }
             State::Finished(_) => vec![],
         }
     }
 
-    pub fn playout<R: rand::Rng>(&self, mut rng: &mut R) -> u32 {
+    /// Picks a uniformly random player move, or a dealer card weighted by the shoe, without
+    /// building the move list. Returns `None` once the game is finished.
+    pub fn random_move<R: rand::Rng>(
+        &self,
+        rules: &Rules,
+        shoe: &card::Shoe,
+        rng: &mut R,
+    ) -> Option<Move> {
+        const COLOURS: [Move; 2] = [
+            Move::Colour(card::Colour::Red),
+            Move::Colour(card::Colour::Black),
+        ];
+        const HI_LOS: [Move; 4] = [
+            Move::HiLo(HiLo::Higher),
+            Move::HiLo(HiLo::Lower),
+            Move::Finish,
+            Move::Same,
+        ];
+        const IN_OUTS: [Move; 4] = [
+            Move::InOut(InOut::Inside),
+            Move::InOut(InOut::Outside),
+            Move::Finish,
+            Move::Same,
+        ];
+        const SUITS: [Move; 5] = [
+            Move::Suit(card::Suit::Hearts),
+            Move::Suit(card::Suit::Diamonds),
+            Move::Suit(card::Suit::Clubs),
+            Move::Suit(card::Suit::Spades),
+            Move::Finish,
+        ];
+        let guesses = if rules.ties == TiePolicy::Same { 4 } else { 3 };
+
+        match *self {
+            State::Start => COLOURS.choose(rng).copied(),
+            State::Stage1PlayerPicked(_) => Some(Move::Card(shoe.draw(&[], rng))),
+            State::Stage1DealerPicked(_, _) => HI_LOS[..guesses].choose(rng).copied(),
+            State::Stage2PlayerPicked(card, _) => Some(Move::Card(shoe.draw(&[card], rng))),
+            State::Stage2DealerPicked(_, _, _) => IN_OUTS[..guesses].choose(rng).copied(),
+            State::Stage3PlayerPicked(card, card1, _) => {
+                Some(Move::Card(shoe.draw(&[card, card1], rng)))
+            }
+            State::Stage3DealerPicked(_, _, _, _) => SUITS.choose(rng).copied(),
+            State::Stage4PlayerPicked(card, card1, card2, _) => {
+                Some(Move::Card(shoe.draw(&[card, card1, card2], rng)))
+            }
+            State::Finished(_) => None,
+        }
+    }
+
+    pub fn playout<R: rand::Rng>(&self, rules: &Rules, shoe: &card::Shoe, rng: &mut R) -> u32 {
         let mut game = *self;
         loop {
             if let State::Finished(x) = game {
                 return x;
             }
-            let valid_moves = game.get_valid_moves();
-            let mov = valid_moves.choose(&mut rng).unwrap();
-            game = game.apply_move(*mov).unwrap();
+            let mov = game.random_move(rules, shoe, rng).unwrap();
+            game = game.apply_move(mov, rules).unwrap();
         }
     }
 
-    pub fn is_terminal(&self) -> bool {
-        match self {
-            Self::Finished(_) => true,
-            _ => false,
+    pub fn map_suits(&self, map: &card::SuitMap) -> Self {
+        match *self {
+            State::Start => State::Start,
+            State::Stage1PlayerPicked(colour) => State::Stage1PlayerPicked(map.colour(colour)),
+            State::Stage1DealerPicked(colour, card) => {
+                State::Stage1DealerPicked(map.colour(colour), map.card(card))
+            }
+            State::Stage2PlayerPicked(card, hi_lo) => {
+                State::Stage2PlayerPicked(map.card(card), hi_lo)
+            }
+            State::Stage2DealerPicked(card, hi_lo, card1) => {
+                State::Stage2DealerPicked(map.card(card), hi_lo, map.card(card1))
+            }
+            State::Stage3PlayerPicked(card, card1, in_out) => {
+                State::Stage3PlayerPicked(map.card(card), map.card(card1), in_out)
+            }
+            State::Stage3DealerPicked(card, card1, in_out, card2) => {
+                State::Stage3DealerPicked(map.card(card), map.card(card1), in_out, map.card(card2))
+            }
+            State::Stage4PlayerPicked(card, card1, card2, suit) => State::Stage4PlayerPicked(
+                map.card(card),
+                map.card(card1),
+                map.card(card2),
+                map.suit(suit),
+            ),
+            State::Finished(x) => State::Finished(x),
         }
     }
 
+    /// The smallest state reachable through one of `symmetries`, along with the map that produces
+    /// it. Two states with the same canonical form have the same value and equivalent moves.
+    pub fn canonical(&self, symmetries: &[card::SuitMap]) -> (Self, card::SuitMap) {
+        symmetries
+            .iter()
+            .map(|map| (self.map_suits(map), *map))
+            .min_by_key(|(state, _)| *state)
+            .unwrap()
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Finished(_))
+    }
+
     pub fn is_dealer_turn(&self) -> bool {
         match self {
             State::Start => false,
@@ -226,15 +369,27 @@ impl State {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Move {
     Colour(card::Colour),
     HiLo(HiLo),
     InOut(InOut),
     Suit(card::Suit),
     Card(card::Card),
+    /// Bets that the dealer's card ties, under `TiePolicy::Same`.
+    Same,
     Finish,
 }
+impl Move {
+    pub fn map_suits(&self, map: &card::SuitMap) -> Self {
+        match *self {
+            Move::Colour(colour) => Move::Colour(map.colour(colour)),
+            Move::Suit(suit) => Move::Suit(map.suit(suit)),
+            Move::Card(card) => Move::Card(map.card(card)),
+            mov => mov,
+        }
+    }
+}
 impl FromStr for Move {
     type Err = &'static str;
 
@@ -251,6 +406,7 @@ impl FromStr for Move {
             "diamonds" => Ok(Move::Suit(card::Suit::Diamonds)),
             "clubs" => Ok(Move::Suit(card::Suit::Clubs)),
             "spades" => Ok(Move::Suit(card::Suit::Spades)),
+            "same" => Ok(Move::Same),
             "finish" => Ok(Move::Finish),
             _ => {
                 let words: Vec<&str> = s.split_ascii_whitespace().collect();
@@ -293,6 +449,7 @@ impl fmt::Display for Move {
             Move::InOut(in_out) => in_out.fmt(f),
             Move::Suit(suit) => suit.fmt(f),
             Move::Card(card) => card.fmt(f),
+            Move::Same => write!(f, "Same"),
             Move::Finish => write!(f, "Finish"),
         }
     }
@@ -1,21 +1,33 @@
+mod bench;
+mod book;
 mod card;
+mod check;
 mod game;
 mod node;
+mod policy;
+mod rng;
+mod rules;
+mod search;
+mod snapshot;
+mod solver;
 
 use std::{
-    io,
+    io, mem,
+    path::PathBuf,
     sync::{
-        Arc, RwLock,
-        atomic::{self, AtomicBool},
+        Arc, Mutex, RwLock,
+        atomic::{self, AtomicBool, AtomicU64},
     },
-    thread,
+    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
-use crossterm::event::{Event, KeyCode, poll, read};
+use book::Book;
+use card::{Card, Shoe, SuitMap};
+use crossterm::event::{Event, KeyCode, KeyModifiers, poll, read};
 use game::Move;
 use itertools::Itertools;
-use node::Node;
+use node::{MoveStats, Parallelism, Tree};
 use ratatui::{
     DefaultTerminal, Frame,
     buffer::Buffer,
@@ -24,38 +36,133 @@ use ratatui::{
     text::Line,
     widgets::{Block, Paragraph, Widget},
 };
+use rules::Rules;
+use search::SearchConfig;
+use snapshot::Snapshot;
+use solver::Solver;
 use tui_textarea::{CursorMove, TextArea};
 
+/// Exact move values for the state they were solved from.
+type ExactMoves = Option<(game::State, Vec<(Move, f64)>)>;
+
 struct App<'a> {
-    root: Arc<RwLock<Node>>,
+    trees: Vec<Arc<RwLock<Tree>>>,
+    rules: Rules,
+    /// Cards left in the dealer's shoe before this round, carried over between rounds until a
+    /// reshuffle.
+    shoe: Shoe,
+    round_cards: Vec<Card>,
+    /// Relabels suits from the real game into the frame of the canonical root node.
+    frame: SuitMap,
     current_input: TextArea<'a>,
     poll_time: Duration,
     last_attempt: Instant,
-    best_moves: Vec<(Move, f64)>,
+    /// The most visited moves in the real frame, with their share of the root's visits.
+    best_moves: Vec<(MoveStats, f64)>,
+    config: SearchConfig,
+    /// Seeds each search started by the app. It is shown in the title, so a session can be replayed
+    /// by passing it to `--seed`.
+    seed: u64,
+    searches: u64,
+    workers: Vec<JoinHandle<()>>,
     stop_mcts: Arc<AtomicBool>,
+    /// Iterations completed by all workers, sampled every poll to show the combined rate.
+    iterations: Arc<AtomicU64>,
+    last_iterations: u64,
+    iteration_rate: f64,
+    solver: Arc<Mutex<Solver>>,
+    exact_moves: Arc<RwLock<ExactMoves>>,
+    /// Solved opening positions, seeding the search wherever they apply.
+    book: Option<Arc<Book>>,
     cached_state: game::State,
+    /// Bytes freed by compacting the trees after the last move.
+    reclaimed: usize,
+    /// Nodes and estimated bytes across all trees, sampled every poll.
+    tree_size: (usize, usize),
+    /// Where Ctrl+S saves the session.
+    snapshot_path: PathBuf,
+    /// The outcome of the last save, shown under the tree size.
+    status: String,
     exit: bool,
 }
 impl<'a> App<'a> {
-    fn new(root: Arc<RwLock<Node>>) -> App<'a> {
+    fn new(rules: Rules, shoe: Shoe, config: SearchConfig, snapshot_path: PathBuf) -> App<'a> {
         App {
-            root,
+            trees: node::new_trees(
+                config.parallelism,
+                config.threads,
+                &shoe,
+                game::State::Start,
+            )
+            .0,
+            rules,
+            shoe: shoe.clone(),
+            round_cards: vec![],
+            frame: SuitMap::IDENTITY,
             current_input: TextArea::default(),
             poll_time: Duration::from_millis(100),
             last_attempt: Instant::now(),
             best_moves: vec![],
+            config,
+            seed: config.seed.unwrap_or_else(rand::random),
+            searches: 0,
+            workers: vec![],
             stop_mcts: Arc::new(AtomicBool::new(false)),
+            iterations: Arc::new(AtomicU64::new(0)),
+            last_iterations: 0,
+            iteration_rate: 0.0,
+            solver: Arc::new(Mutex::new(Solver::new(rules, shoe))),
+            exact_moves: Arc::new(RwLock::new(None)),
+            book: None,
             cached_state: game::State::Start,
+            reclaimed: 0,
+            tree_size: (0, 0),
+            snapshot_path,
+            status: String::new(),
             exit: false,
         }
     }
 
+    /// Resumes a saved session, searching on from the saved trees. The parallelism they were
+    /// saved with overrides `config`, and root parallel trees get one worker each again.
+    fn from_snapshot(snapshot: Snapshot, config: SearchConfig, snapshot_path: PathBuf) -> App<'a> {
+        let config = SearchConfig {
+            parallelism: snapshot.parallelism,
+            threads: match snapshot.parallelism {
+                Parallelism::Tree => config.threads,
+                Parallelism::Root => snapshot.trees.len(),
+            },
+            ..config
+        };
+        let mut app = App::new(snapshot.rules, snapshot.shoe, config, snapshot_path);
+        app.trees = snapshot
+            .trees
+            .into_iter()
+            .map(|tree| Arc::new(RwLock::new(tree)))
+            .collect();
+        app.round_cards = snapshot.round_cards;
+        app.frame = snapshot.frame;
+        app.cached_state = snapshot.state;
+        app
+    }
+
     fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
-        self.start_mcts_thread();
+        self.start_mcts_threads();
+        self.start_solver_thread();
 
         while !self.exit {
-            if self.last_attempt.elapsed() > self.poll_time && !self.cached_state.is_dealer_turn() {
-                self.best_moves = self.get_best_moves(5);
+            if self.last_attempt.elapsed() > self.poll_time {
+                let iterations = self.iterations.load(atomic::Ordering::Relaxed);
+                self.iteration_rate = (iterations - self.last_iterations) as f64
+                    / self.last_attempt.elapsed().as_secs_f64();
+                self.last_iterations = iterations;
+                self.tree_size = self.trees.iter().fold((0, 0), |(nodes, bytes), tree| {
+                    let tree = tree.read().unwrap();
+                    (nodes + tree.node_count(), bytes + tree.memory())
+                });
+                if !self.cached_state.is_dealer_turn() {
+                    self.best_moves = self.get_best_moves(5);
+                }
                 self.last_attempt = Instant::now();
             }
             terminal.draw(|frame| self.draw(frame))?;
@@ -68,57 +175,272 @@ impl<'a> App<'a> {
         frame.render_widget(self, frame.area());
     }
 
-    fn get_best_moves(&self, number: usize) -> Vec<(Move, f64)> {
-        self.root
-            .read()
-            .unwrap()
-            .get_best_moves()
-            .iter()
-            .k_largest_by(number, |(_, x), (_, y)| x.total_cmp(y))
-            .copied()
+    fn get_best_moves(&self, number: usize) -> Vec<(MoveStats, f64)> {
+        let to_real = self.frame.inverse();
+        let (root_visits, stats) = node::merge_root_stats(&self.trees);
+        stats
+            .into_iter()
+            .k_largest_by(number, |x, y| x.visits.total_cmp(&y.visits))
+            .map(|stats| {
+                let share = stats.visits / root_visits;
+                (
+                    MoveStats {
+                        mov: stats.mov.map_suits(&to_real),
+                        ..stats
+                    },
+                    share,
+                )
+            })
             .collect()
     }
 
-    fn start_mcts_thread(&mut self) {
-        let root = self.root.clone();
-        let stop = self.stop_mcts.clone();
+    fn start_mcts_threads(&mut self) {
+        let seed = rng::derive_seed(self.seed, self.searches);
+        self.searches += 1;
+        for i in 0..self.config.threads {
+            let tree = self.trees[i % self.trees.len()].clone();
+            let mut worker = self.config.worker(self.rules, &self.shoe, seed, i);
+            worker.book = self.book.clone();
+            let stop = self.stop_mcts.clone();
+            let iterations = self.iterations.clone();
+            self.workers.push(thread::spawn(move || {
+                Tree::mcts(tree, worker, stop, iterations, u64::MAX);
+            }));
+        }
+    }
+
+    fn stop_mcts_threads(&mut self) {
+        self.stop_mcts.store(true, atomic::Ordering::Release);
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+        self.stop_mcts.store(false, atomic::Ordering::Release);
+    }
+
+    fn start_solver_thread(&mut self) {
+        let state = self.cached_state;
+        if state.is_dealer_turn() || state.is_terminal() {
+            return;
+        }
+
+        let solver = self.solver.clone();
+        let exact_moves = self.exact_moves.clone();
         thread::spawn(move || {
-            Node::mcts(root, stop.clone());
-            stop.store(false, atomic::Ordering::Release);
+            let moves = solver.lock().unwrap().move_values(state);
+            *exact_moves.write().unwrap() = Some((state, moves));
         });
     }
 
+    /// Exact move values for the current state, or none until the solver has finished.
+    fn current_exact_moves(&self) -> Vec<(Move, f64)> {
+        self.exact_moves
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(state, _)| *state == self.cached_state)
+            .map_or_else(Vec::new, |(_, moves)| moves.clone())
+    }
+
+    fn exact_value(&self, mov: Move) -> Option<f64> {
+        self.current_exact_moves()
+            .into_iter()
+            .find(|(m, _)| *m == mov)
+            .map(|(_, x)| x)
+    }
+
     fn handle_events(&mut self) -> io::Result<()> {
-        if let Ok(true) = poll(Duration::from_secs(0)) {
-            if let Event::Key(key) = read()? {
-                if key.code == KeyCode::Esc {
-                    self.exit = true;
-                } else if key.code == KeyCode::Enter {
-                    if self.try_set_new_root() {
-                        self.current_input.move_cursor(CursorMove::Head);
-                        self.current_input.delete_line_by_end();
-                        self.stop_mcts.store(true, atomic::Ordering::Release);
-                        while self.stop_mcts.load(atomic::Ordering::Relaxed) {}
-                        self.start_mcts_thread();
+        if let Ok(true) = poll(Duration::from_secs(0))
+            && let Event::Key(key) = read()?
+        {
+            if key.code == KeyCode::Esc {
+                self.exit = true;
+            } else if key.code == KeyCode::Char('s') && key.modifiers == KeyModifiers::CONTROL {
+                self.save();
+            } else if key.code == KeyCode::Enter {
+                // Workers hold node ids across their playouts, so they must stop before the
+                // trees are re-rooted and compacted.
+                self.stop_mcts_threads();
+                let accepted = match self.current_input.lines()[0].trim() {
+                    "next" => {
+                        self.next_round(false);
+                        true
+                    }
+                    "reshuffle" => {
+                        self.next_round(true);
+                        true
                     }
-                } else {
-                    self.current_input.input(key);
+                    _ => self.try_set_new_root(),
+                };
+                if accepted {
+                    self.current_input.move_cursor(CursorMove::Head);
+                    self.current_input.delete_line_by_end();
+                    self.start_solver_thread();
                 }
+                self.start_mcts_threads();
+            } else {
+                self.current_input.input(key);
             }
         }
         Ok(())
     }
 
     fn try_set_new_root(&mut self) -> bool {
-        let line = &self.current_input.lines()[0];
-        if let Ok(mov) = line.parse() {
-            if let Some(new_node) = Node::find_child(self.root.clone(), mov) {
-                self.root = new_node;
-                self.cached_state = self.root.read().unwrap().state;
-                return true;
+        let Ok(mov) = self.current_input.lines()[0].parse::<Move>() else {
+            return false;
+        };
+
+        let tree_mov = mov.map_suits(&self.frame);
+        let maps = self
+            .trees
+            .iter()
+            .map(|tree| {
+                tree.write().unwrap().advance(
+                    tree_mov,
+                    &self.rules,
+                    &self.shoe,
+                    self.book.as_deref(),
+                )
+            })
+            .collect::<Option<Vec<SuitMap>>>();
+        let Some(map) = maps.and_then(|maps| maps.first().copied()) else {
+            return false;
+        };
+
+        self.reclaimed = 0;
+        for tree in &self.trees {
+            let garbage = tree.write().unwrap().compact();
+            self.reclaimed += garbage.reclaimed;
+            thread::spawn(move || drop(garbage));
+        }
+
+        self.frame = self.frame.then(&map);
+        self.cached_state = self.cached_state.apply_move(mov, &self.rules).unwrap();
+        if let Move::Card(card) = mov {
+            self.round_cards.push(card);
+        }
+        true
+    }
+
+    /// Saves the trees and the round they were searched from to the snapshot path. Workers wait
+    /// on the read locks while it is written rather than being stopped.
+    fn save(&mut self) {
+        let guards = self
+            .trees
+            .iter()
+            .map(|tree| tree.read().unwrap())
+            .collect::<Vec<_>>();
+        let snapshot = Snapshot {
+            rules: self.rules,
+            shoe: self.shoe.clone(),
+            round_cards: self.round_cards.clone(),
+            state: self.cached_state,
+            frame: self.frame,
+            parallelism: self.config.parallelism,
+            trees: guards.iter().map(|tree| &**tree).collect(),
+        };
+        self.status = match snapshot.save(&self.snapshot_path) {
+            Ok(()) => format!("Saved to {}", self.snapshot_path.display()),
+            Err(e) => format!("Saving to {} failed: {}", self.snapshot_path.display(), e),
+        };
+    }
+
+    fn tree_line(&self) -> Line<'static> {
+        let (nodes, bytes) = self.tree_size;
+        let mut line = format!("Tree: {} nodes, {:.1} MB", nodes, bytes as f64 / 1e6);
+        if self.config.max_nodes != usize::MAX {
+            line += &format!(" (limit {} nodes per tree)", self.config.max_nodes);
+        }
+        line += &format!(
+            ", {:.1} MB freed by the last move",
+            self.reclaimed as f64 / 1e6
+        );
+        Line::from(line)
+    }
+
+    /// One line per recommended move, led by the exact best move once it is known and by what
+    /// cashing out pays now when that is allowed.
+    fn move_lines(&self) -> Vec<Line<'static>> {
+        let mut lines = vec![];
+        let exact_moves = self.current_exact_moves();
+        if let Some((mov, value)) = exact_moves.iter().max_by(|(_, x), (_, y)| x.total_cmp(y)) {
+            lines.push(Line::from(format!(
+                "Exact best move: {} at x{:.3}",
+                mov, value
+            )));
+        }
+        if let Some(game::State::Finished(payout)) =
+            self.cached_state.apply_move(Move::Finish, &self.rules)
+        {
+            // The search's means are biased low by exploration, so the exact value is preferred.
+            let exact = exact_moves
+                .iter()
+                .filter(|(mov, _)| *mov != Move::Finish)
+                .max_by(|(_, x), (_, y)| x.total_cmp(y));
+            let estimate = self
+                .best_moves
+                .iter()
+                .filter(|(stats, _)| stats.mov != Move::Finish && stats.visits > 0.0)
+                .max_by(|(x, _), (y, _)| x.mean().total_cmp(&y.mean()))
+                .map(|(stats, _)| (stats.mov, self.rules.denormalise(stats.mean())));
+            lines.push(Line::from(match (exact, estimate) {
+                (Some(&(mov, value)), _) => format!(
+                    "Finish pays x{}, continuing with {} is worth x{:.3}",
+                    payout, mov, value
+                ),
+                (None, Some((mov, value))) => format!(
+                    "Finish pays x{}, continuing with {} is estimated at x{:.3}",
+                    payout, mov, value
+                ),
+                (None, None) => format!("Finish pays x{}", payout),
+            }));
+        }
+
+        for (stats, share) in &self.best_moves {
+            let (lower, upper) = stats.interval();
+            let mut line = format!(
+                "{}: x{:.3} expected (95% x{:.3} to x{:.3})",
+                stats.mov,
+                self.rules.denormalise(stats.mean()),
+                self.rules.denormalise(lower),
+                self.rules.denormalise(upper)
+            );
+            if let Some(survival) = stats.survival {
+                line += &format!(", {:.1}% survive the next card", survival * 100.0);
             }
+            line += &format!(", {} visits ({:.1}%)", stats.visits, share * 100.0);
+            if let Some(ev) = self.exact_value(stats.mov) {
+                line += &format!(" (EV {:.3})", ev);
+            }
+            lines.push(Line::from(line));
         }
-        false
+        lines
+    }
+
+    /// Starts a fresh round, taking the cards dealt in this one out of the shoe unless the dealer
+    /// reshuffled. A shoe too small to deal a whole round is reshuffled anyway.
+    fn next_round(&mut self, reshuffle: bool) {
+        for card in self.round_cards.drain(..) {
+            self.shoe.remove(card);
+        }
+        if reshuffle || self.shoe.total() < 4 {
+            self.shoe = Shoe::new(self.rules.decks);
+        }
+
+        let (trees, frame) = node::new_trees(
+            self.config.parallelism,
+            self.config.threads,
+            &self.shoe,
+            game::State::Start,
+        );
+        // The finished round's trees are the largest the session has, so free them off the UI
+        // thread like the garbage left by compaction.
+        let garbage = mem::replace(&mut self.trees, trees);
+        thread::spawn(move || drop(garbage));
+        self.frame = frame;
+        self.cached_state = game::State::Start;
+        self.best_moves.clear();
+        self.solver = Arc::new(Mutex::new(Solver::new(self.rules, self.shoe.clone())));
+        self.exact_moves = Arc::new(RwLock::new(None));
     }
 }
 impl<'a> Widget for &mut App<'a> {
@@ -126,7 +448,16 @@ impl<'a> Widget for &mut App<'a> {
     where
         Self: Sized,
     {
-        let title = Line::from("Ride the bus");
+        let title = Line::from(format!(
+            "Ride the bus ({} cards in shoe, {:.0} iterations/s on {} threads, {:?} parallel, {}, \
+             seed {})",
+            self.shoe.total(),
+            self.iteration_rate,
+            self.config.threads,
+            self.config.parallelism,
+            self.config.policy,
+            self.seed
+        ));
 
         let layout = Layout::default()
             .direction(ratatui::layout::Direction::Vertical)
@@ -150,16 +481,10 @@ impl<'a> Widget for &mut App<'a> {
                     "Game finished! Multiplier: {}",
                     multiplier
                 ))],
-                Line::from(""),
+                Line::from("Enter next, or reshuffle if the dealer reshuffled"),
             )
         } else {
-            (
-                self.best_moves
-                    .iter()
-                    .map(|(m, x)| Line::from(format!("{} {:.3}", m, x)))
-                    .collect::<Vec<Line>>(),
-                Line::from("Enter player's move"),
-            )
+            (self.move_lines(), Line::from("Enter player's move"))
         };
 
         let text_block = Block::bordered().title(prompt);
@@ -167,17 +492,171 @@ impl<'a> Widget for &mut App<'a> {
         self.current_input.set_block(text_block);
         self.current_input.render(layout[1], buf);
 
-        Paragraph::new(move_lines).render(layout[0], buf);
+        let mut lines = vec![
+            self.tree_line(),
+            Line::from(self.status.clone()),
+            Line::from(""),
+        ];
+        lines.extend(move_lines);
+        Paragraph::new(lines).render(layout[0], buf);
         outer_block.render(area, buf);
     }
 }
 
+struct Options {
+    rules: Rules,
+    config: SearchConfig,
+    bench: bool,
+    search: Option<search::Budget>,
+    check: Option<search::Budget>,
+    load: Option<PathBuf>,
+    snapshot: PathBuf,
+    book: Option<PathBuf>,
+    write_book: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rules: Rules::default(),
+        config: SearchConfig {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            ..SearchConfig::default()
+        },
+        bench: false,
+        search: None,
+        check: None,
+        load: None,
+        snapshot: PathBuf::from("ride-the-bus.snapshot"),
+        book: None,
+        write_book: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--payouts" => {
+                let value = args.next().ok_or("--payouts needs a value")?;
+                options.rules.payouts = value.parse()?;
+            }
+            "--ties" => {
+                let value = args.next().ok_or("--ties needs a value")?;
+                options.rules.ties = value.parse()?;
+            }
+            "--decks" => {
+                let value = args.next().ok_or("--decks needs a value")?;
+                options.rules.decks = match value.parse() {
+                    Ok(decks) if decks > 0 => decks,
+                    _ => return Err("--decks must be between 1 and 255".to_string()),
+                };
+            }
+            "--threads" => {
+                let value = args.next().ok_or("--threads needs a value")?;
+                options.config.threads = match value.parse() {
+                    Ok(threads) if threads > 0 => threads,
+                    _ => return Err("--threads must be at least 1".to_string()),
+                };
+            }
+            "--parallel" => {
+                let value = args.next().ok_or("--parallel needs a value")?;
+                options.config.parallelism = value.parse()?;
+            }
+            "--policy" => {
+                let value = args.next().ok_or("--policy needs a value")?;
+                options.config.policy = value.parse()?;
+            }
+            "--fpu" => {
+                let value = args.next().ok_or("--fpu needs a value")?;
+                options.config.fpu = value.parse()?;
+            }
+            "--max-nodes" => {
+                let value = args.next().ok_or("--max-nodes needs a value")?;
+                options.config.max_nodes = match value.parse() {
+                    Ok(nodes) if nodes > 0 => nodes,
+                    _ => return Err("--max-nodes must be at least 1".to_string()),
+                };
+            }
+            "--rng" => {
+                let value = args.next().ok_or("--rng needs a value")?;
+                options.config.rng = value.parse()?;
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                options.config.seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| "--seed must be a non-negative integer")?,
+                );
+            }
+            "--bench" => options.bench = true,
+            "--check" => {
+                let value = args.next().ok_or("--check needs a value")?;
+                options.check = Some(value.parse()?);
+            }
+            "--search" => {
+                let value = args.next().ok_or("--search needs a value")?;
+                options.search = Some(value.parse()?);
+            }
+            "--load" => {
+                let value = args.next().ok_or("--load needs a value")?;
+                options.load = Some(value.into());
+            }
+            "--snapshot" => {
+                let value = args.next().ok_or("--snapshot needs a value")?;
+                options.snapshot = value.into();
+            }
+            "--book" => {
+                let value = args.next().ok_or("--book needs a value")?;
+                options.book = Some(value.into());
+            }
+            "--write-book" => {
+                let value = args.next().ok_or("--write-book needs a value")?;
+                options.write_book = Some(value.into());
+            }
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
 fn main() -> io::Result<()> {
-    let mut terminal = ratatui::init();
+    let options = parse_args().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let rules = options.rules;
+
+    if options.bench {
+        bench::playouts(&rules, &Shoe::new(rules.decks), Duration::from_secs(3));
+        return Ok(());
+    }
+    if let Some(path) = options.write_book {
+        let book = Book::generate(rules, Shoe::new(rules.decks));
+        book.save(&path)?;
+        println!("Wrote {} positions to {}", book.len(), path.display());
+        return Ok(());
+    }
+    if let Some(budget) = options.check {
+        let shoe = Shoe::new(rules.decks);
+        if !check::against_solver(rules, &shoe, budget, options.config) {
+            return Err(io::Error::other("search disagreed with the exact solver"));
+        }
+        return Ok(());
+    }
+    if let Some(budget) = options.search {
+        search::print(rules, &Shoe::new(rules.decks), budget, options.config);
+        return Ok(());
+    }
 
-    let root = Node::start();
+    let mut app = match options.load {
+        Some(path) => App::from_snapshot(Snapshot::load(&path)?, options.config, options.snapshot),
+        None => App::new(
+            rules,
+            Shoe::new(rules.decks),
+            options.config,
+            options.snapshot,
+        ),
+    };
+    if let Some(path) = options.book {
+        app.book = Some(Arc::new(Book::load(&path)?));
+    }
 
-    let mut app = App::new(root);
+    let mut terminal = ratatui::init();
     app.run(&mut terminal)?;
     ratatui::restore();
     Ok(())
@@ -1,140 +1,804 @@
 use std::{
-    f64::consts::SQRT_2,
+    collections::HashMap,
+    mem,
+    ops::Range,
+    str::FromStr,
     sync::{
         Arc, RwLock,
-        atomic::{self, AtomicBool},
+        atomic::{self, AtomicBool, AtomicU64},
     },
 };
 
-use crate::game::{Move, State};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-pub struct Node {
+use crate::{
+    book::Book,
+    card::{Shoe, SuitMap},
+    game::{Move, State},
+    policy::{ChildStats, Fpu, SelectionPolicy},
+    rng::SearchRng,
+    rules::Rules,
+};
+
+pub type NodeId = u32;
+
+/// Marks an edge whose move has not been tried yet.
+const UNEXPANDED: NodeId = NodeId::MAX;
+
+/// Visits a node or move needs before `Tree::value` trusts its backed-up value over its mean.
+const BACKUP_VISITS: f64 = 30.0;
+
+/// Playouts a book value counts as when it seeds a move, enough to steady the first
+/// recommendations while a long search still outweighs it.
+const BOOK_VISITS: f64 = 1000.0;
+
+/// How several workers split the search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parallelism {
+    /// Every worker searches one shared tree. Workers take turns holding its lock for all but
+    /// the short playout, so this mode barely scales past one worker; `Root` does.
+    Tree,
+    /// Every worker searches its own tree, and the root statistics are merged.
+    Root,
+}
+impl FromStr for Parallelism {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tree" => Ok(Parallelism::Tree),
+            "root" => Ok(Parallelism::Root),
+            _ => Err("Parallelism must be tree or root"),
+        }
+    }
+}
+
+/// Search statistics for one move out of the root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveStats {
+    pub mov: Move,
+    pub visits: f64,
+    pub reward: f64,
+    pub reward_squared: f64,
+    /// Chance that the card the dealer deals after this move keeps the game going, for moves
+    /// followed by a draw.
+    pub survival: Option<f64>,
+    /// Normalised value of the move with the tree's best replies taken rather than averaged in,
+    /// see `Tree::value`.
+    pub value: f64,
+}
+impl MoveStats {
+    /// Mean normalised reward of the playouts through this move.
+    pub fn mean(&self) -> f64 {
+        self.reward / self.visits
+    }
+
+    /// Standard error of the mean, from the sample variance of the playout rewards.
+    pub fn std_err(&self) -> f64 {
+        if self.visits < 2.0 {
+            return f64::INFINITY;
+        }
+        let variance =
+            (self.reward_squared - self.reward * self.reward / self.visits) / (self.visits - 1.0);
+        (variance.max(0.0) / self.visits).sqrt()
+    }
+
+    /// The 95% confidence interval of the mean reward.
+    pub fn interval(&self) -> (f64, f64) {
+        let half_width = 1.96 * self.std_err();
+        (self.mean() - half_width, self.mean() + half_width)
+    }
+}
+
+/// A move out of a node. Children are stored in canonical form and may be shared between
+/// parents. A dealer edge stands for every card that leads to the same canonical child, with `mov`
+/// one of those cards and `weight` the number of ways any of them can be drawn from the shoe.
+/// Until the move is first tried, `node` is `UNEXPANDED`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Edge {
+    mov: Move,
+    weight: u32,
+    /// Prior probability of a player move, from its chance of surviving the next card.
+    prior: f64,
+    /// Chance that a player move survives the dealer's next card, if one is drawn after it.
+    survival: Option<f64>,
+    reward: f64,
+    /// Sum of squared playout rewards, for the variance of the mean.
+    reward_squared: f64,
+    visits: f64,
+    /// Workers currently playing out through this edge.
+    virtual_loss: f64,
+    /// The book's normalised value of the move, if it seeded the edge with `BOOK_VISITS`.
+    book: Option<f64>,
+    node: NodeId,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Node {
     reward: f64,
+    reward_squared: f64,
     visits: f64,
-    mov: Option<Move>,
-    pub state: State,
-    parent: Option<Arc<RwLock<Node>>>,
-    pub children: Vec<Arc<RwLock<Node>>>,
+    /// Workers currently playing out through this node, counted as losses until they report
+    /// back so that other workers are steered onto different branches.
+    virtual_loss: f64,
+    state: State,
+    /// The node's edges are `Tree::edges[first_child..first_child + child_count]`, reserved for
+    /// every legal move the first time the node is expanded. The first `expanded` of them lead to
+    /// child nodes, the rest are still untried.
+    first_child: u32,
+    child_count: u32,
+    expanded: u32,
 }
 impl Node {
-    pub fn new(
-        previous_state: State,
-        mov: Move,
-        parent: Arc<RwLock<Node>>,
-    ) -> Option<Arc<RwLock<Node>>> {
-        previous_state.apply_move(mov).map(|state| {
-            Arc::new(RwLock::new(Node {
-                reward: 0.0,
-                visits: 0.0,
-                mov: Some(mov),
-                state,
-                parent: Some(parent),
-                children: vec![],
-            }))
+    fn new(state: State) -> Node {
+        Node {
+            reward: 0.0,
+            reward_squared: 0.0,
+            visits: 0.0,
+            virtual_loss: 0.0,
+            state,
+            first_child: 0,
+            child_count: 0,
+            expanded: 0,
+        }
+    }
+
+    /// Every reserved edge, tried or not.
+    fn children(&self) -> Range<usize> {
+        self.first_child as usize..(self.first_child + self.child_count) as usize
+    }
+
+    /// The edges that lead to child nodes.
+    fn expanded(&self) -> Range<usize> {
+        self.first_child as usize..(self.first_child + self.expanded) as usize
+    }
+
+    fn is_fully_expanded(&self) -> bool {
+        self.child_count > 0 && self.expanded == self.child_count
+    }
+}
+
+/// Everything one search worker owns besides the tree.
+pub struct Worker {
+    pub rules: Rules,
+    pub shoe: Shoe,
+    pub policy: Box<dyn SelectionPolicy>,
+    pub fpu: Fpu,
+    /// Once the tree holds this many nodes, the worker stops expanding and only plays out from
+    /// the leaves it reaches.
+    pub max_nodes: usize,
+    pub rng: SearchRng,
+    /// Exact values that seed the moves of positions the book covers.
+    pub book: Option<Arc<Book>>,
+}
+
+/// The weighted share of the cards a dealer state can deal that do not lose the game outright.
+/// States where the player moves next survive by definition.
+fn survival_chance(state: State, rules: &Rules, shoe: &Shoe) -> f64 {
+    if !state.is_dealer_turn() {
+        return 1.0;
+    }
+    let (alive, total) = state.get_weighted_moves(rules, shoe).into_iter().fold(
+        (0, 0),
+        |(alive, total), (mov, weight)| {
+            let lost = state.apply_move(mov, rules) == Some(State::Finished(0));
+            (alive + if lost { 0 } else { weight }, total + weight)
+        },
+    );
+    alive as f64 / total as f64
+}
+
+/// The search tree, with nodes and edges stored in flat arenas and referenced by index.
+#[derive(Serialize, Deserialize)]
+pub struct Tree {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    /// Nodes keyed by canonical state, so positions that only differ by suit share one subtree.
+    /// Not saved, as `restore` rebuilds it from the nodes.
+    #[serde(skip)]
+    table: HashMap<State, NodeId>,
+    symmetries: Vec<SuitMap>,
+    root: NodeId,
+}
+impl Tree {
+    /// Builds a tree rooted at `state`, returning it with the map from the real frame into the
+    /// root's canonical frame.
+    pub fn from_state(shoe: &Shoe, state: State) -> (Tree, SuitMap) {
+        let mut tree = Tree {
+            nodes: vec![],
+            edges: vec![],
+            table: HashMap::new(),
+            symmetries: shoe.symmetries(),
+            root: 0,
+        };
+        let (state, map) = state.canonical(&tree.symmetries);
+        tree.root = tree.get_or_insert(state);
+        (tree, map)
+    }
+
+    /// Finishes loading a saved tree: rebuilds the transposition table and drops the virtual loss
+    /// of workers that were mid-playout when it was saved.
+    pub fn restore(&mut self) {
+        self.table = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(id, node)| (node.state, id as NodeId))
+            .collect();
+        for node in &mut self.nodes {
+            node.virtual_loss = 0.0;
+        }
+        for edge in &mut self.edges {
+            edge.virtual_loss = 0.0;
+        }
+    }
+
+    fn get_or_insert(&mut self, state: State) -> NodeId {
+        *self.table.entry(state).or_insert_with(|| {
+            self.nodes.push(Node::new(state));
+            (self.nodes.len() - 1) as NodeId
         })
     }
 
-    pub fn start() -> Arc<RwLock<Node>> {
-        Arc::new(RwLock::new(Node {
-            reward: 0.0,
-            visits: 0.0,
-            mov: None,
-            state: State::Start,
-            parent: None,
-            children: vec![],
-        }))
+    /// Selection statistics for the child behind `edge`. The mean comes from the child node, so
+    /// every path into a shared child agrees on it, plus the book's playouts if the edge has them.
+    fn child_stats(&self, edge: &Edge) -> ChildStats {
+        let child = &self.nodes[edge.node as usize];
+        let (mut visits, mut reward, mut reward_squared) = (
+            child.visits + child.virtual_loss,
+            child.reward,
+            child.reward_squared,
+        );
+        if let Some(book) = edge.book {
+            visits += BOOK_VISITS;
+            reward += BOOK_VISITS * book;
+            reward_squared += BOOK_VISITS * book;
+        }
+        let mean = reward / visits;
+        ChildStats {
+            mean,
+            variance: (reward_squared / visits - mean * mean).max(0.0),
+            visits: edge.visits + edge.virtual_loss,
+            prior: edge.prior,
+        }
     }
 
-    fn score_node(&self, root_visits: f64) -> f64 {
-        (self.reward / self.visits) + SQRT_2 * ((root_visits.ln() / self.visits).sqrt())
+    /// Scores the tried children against their parent's own visit count, including workers still
+    /// playing out through it, so exploration shrinks with depth as the parent settles. Returns
+    /// the best score and its edge, or `None` if no move has been tried yet.
+    fn choose_best_child(
+        &self,
+        node: NodeId,
+        policy: &dyn SelectionPolicy,
+        fpu: Fpu,
+        rng: &mut SearchRng,
+    ) -> Option<(f64, usize)> {
+        let current = &self.nodes[node as usize];
+        let parent_visits = current.visits + current.virtual_loss;
+        let mut best = (f64::NEG_INFINITY, None);
+        for index in current.expanded() {
+            let stats = self.child_stats(&self.edges[index]);
+            let score = if stats.visits == 0.0 {
+                fpu.score()
+            } else {
+                policy.score(&stats, parent_visits, rng)
+            };
+            debug_assert!(!score.is_nan(), "NaN score for {:?}", stats);
+            if best.1.is_none() || score.total_cmp(&best.0).is_gt() {
+                best = (score, Some(index));
+            }
+        }
+        best.1.map(|index| (best.0, index))
     }
 
-    fn choose_best_child(&self, root_visits: f64) -> Arc<RwLock<Node>> {
-        self.children
+    /// Dealer nodes are chance nodes: the next child is drawn in proportion to the copies of each
+    /// card left in the shoe, instead of being chosen to maximise our reward.
+    fn sample_chance_child<R: Rng>(&self, node: NodeId, rng: &mut R) -> usize {
+        let children = self.nodes[node as usize].children();
+        let total: u32 = self.edges[children.clone()]
             .iter()
-            .max_by(|x, y| {
-                x.read()
-                    .unwrap()
-                    .score_node(root_visits)
-                    .total_cmp(&y.read().unwrap().score_node(root_visits))
-            })
-            .unwrap()
-            .to_owned()
+            .map(|edge| edge.weight)
+            .sum();
+        let mut target = rng.random_range(0..total);
+        for index in children {
+            let weight = self.edges[index].weight;
+            if target < weight {
+                return index;
+            }
+            target -= weight;
+        }
+        unreachable!()
     }
 
-    fn select_node(root: Arc<RwLock<Node>>) -> Arc<RwLock<Node>> {
-        let mut node = root.clone();
+    /// Walks down the tree, recording every node on the way along with the edge taken out of it.
+    /// Stops at a terminal node, or at a node with a move to try next: a player node whose untried
+    /// moves, scored at the FPU value, beat its best tried child, or a dealer node whose sampled
+    /// card is untried, which is left as the last edge on the path for `expand`.
+    fn select_node(
+        &self,
+        path: &mut Vec<(NodeId, Option<usize>)>,
+        policy: &dyn SelectionPolicy,
+        fpu: Fpu,
+        rng: &mut SearchRng,
+    ) {
+        let mut node = self.root;
 
-        while !node.read().unwrap().state.is_terminal() && !node.read().unwrap().children.is_empty()
-        {
-            let next = node
-                .read()
+        loop {
+            let current = &self.nodes[node as usize];
+            if current.state.is_terminal() || current.child_count == 0 {
+                break;
+            }
+
+            let index = if current.state.is_dealer_turn() {
+                let index = self.sample_chance_child(node, rng);
+                if self.edges[index].node == UNEXPANDED {
+                    path.push((node, Some(index)));
+                    return;
+                }
+                index
+            } else {
+                match self.choose_best_child(node, policy, fpu, rng) {
+                    Some((score, _)) if !current.is_fully_expanded() && fpu.score() > score => {
+                        break;
+                    }
+                    Some((_, index)) => index,
+                    None => break,
+                }
+            };
+            path.push((node, Some(index)));
+            node = self.edges[index].node;
+        }
+
+        path.push((node, None));
+    }
+
+    /// Groups the cards a dealer state can deal by the canonical state they lead to, since cards
+    /// that differ only by a suit symmetry, or that all lose the game, are the same outcome.
+    fn bucket_outcomes(&self, state: State, rules: &Rules, shoe: &Shoe) -> Vec<(Move, u32)> {
+        let mut buckets: Vec<(State, Move, u32)> = vec![];
+        for (mov, weight) in state.get_weighted_moves(rules, shoe) {
+            let (child, _) = state
+                .apply_move(mov, rules)
                 .unwrap()
-                .choose_best_child(root.read().unwrap().visits);
-            node = next;
+                .canonical(&self.symmetries);
+            match buckets.iter_mut().find(|(bucket, _, _)| *bucket == child) {
+                Some((_, _, total)) => *total += weight,
+                None => buckets.push((child, mov, weight)),
+            }
+        }
+        buckets
+            .into_iter()
+            .map(|(_, mov, weight)| (mov, weight))
+            .collect()
+    }
+
+    /// Reserves an edge for every distinct move out of `node`, without creating any children. If
+    /// the book covers the node, each move starts with `BOOK_VISITS` playouts at its exact value,
+    /// spread as widely as rewards with that mean can be.
+    fn reserve_children(&mut self, node: NodeId, rules: &Rules, shoe: &Shoe, book: Option<&Book>) {
+        let state = self.nodes[node as usize].state;
+        let first_child = self.edges.len() as u32;
+        let moves = if state.is_dealer_turn() {
+            self.bucket_outcomes(state, rules, shoe)
+        } else {
+            state.get_weighted_moves(rules, shoe)
+        };
+        for (mov, weight) in moves {
+            let survival = if state.is_dealer_turn() {
+                None
+            } else {
+                let child = state.apply_move(mov, rules).unwrap();
+                child
+                    .is_dealer_turn()
+                    .then(|| survival_chance(child, rules, shoe))
+            };
+            self.edges.push(Edge {
+                mov,
+                weight,
+                prior: survival.unwrap_or(1.0),
+                survival,
+                reward: 0.0,
+                reward_squared: 0.0,
+                visits: 0.0,
+                virtual_loss: 0.0,
+                book: None,
+                node: UNEXPANDED,
+            });
+        }
+
+        let current = &mut self.nodes[node as usize];
+        current.first_child = first_child;
+        current.child_count = self.edges.len() as u32 - first_child;
+
+        let children = current.children();
+        let total: f64 = self.edges[children.clone()].iter().map(|e| e.prior).sum();
+        for edge in &mut self.edges[children.clone()] {
+            edge.prior /= total;
         }
 
-        node
+        let Some(values) = book.and_then(|book| book.moves(state, rules, shoe)) else {
+            return;
+        };
+        for edge in &mut self.edges[children] {
+            let Some(&(_, value)) = values.iter().find(|(mov, _)| *mov == edge.mov) else {
+                continue;
+            };
+            let mean = value / rules.payouts.max() as f64;
+            edge.book = Some(mean);
+            edge.visits += BOOK_VISITS;
+            edge.reward += BOOK_VISITS * mean;
+            edge.reward_squared += BOOK_VISITS * mean;
+
+            let current = &mut self.nodes[node as usize];
+            current.visits += BOOK_VISITS;
+            current.reward += BOOK_VISITS * mean;
+            current.reward_squared += BOOK_VISITS * mean;
+        }
+    }
+
+    /// Creates the child for the untried edge at `index`, first swapping it to the front of the
+    /// untried edges so the tried ones stay contiguous. Returns the edge's new index.
+    fn expand_edge(&mut self, node: NodeId, index: usize, rules: &Rules) -> usize {
+        let current = &self.nodes[node as usize];
+        let state = current.state;
+        let slot = (current.first_child + current.expanded) as usize;
+        self.edges.swap(index, slot);
+
+        let (child_state, _) = state
+            .apply_move(self.edges[slot].mov, rules)
+            .unwrap()
+            .canonical(&self.symmetries);
+        self.edges[slot].node = self.get_or_insert(child_state);
+        self.nodes[node as usize].expanded += 1;
+        slot
     }
 
-    fn expand(node: Arc<RwLock<Node>>) -> Arc<RwLock<Node>> {
-        let possible_moves = node.read().unwrap().state.get_valid_moves();
+    /// Tries one new move out of `node`: the dealer card already sampled by selection if
+    /// `pending` is set, or else a random untried move, so no move is favoured by its position in
+    /// the move list. Returns the index of the new edge.
+    fn expand(
+        &mut self,
+        node: NodeId,
+        pending: Option<usize>,
+        rules: &Rules,
+        shoe: &Shoe,
+        book: Option<&Book>,
+        rng: &mut SearchRng,
+    ) -> usize {
+        if self.nodes[node as usize].child_count == 0 {
+            self.reserve_children(node, rules, shoe, book);
+        }
 
-        let state = node.read().unwrap().state;
-        let children = &mut node.write().unwrap().children;
-        for mov in possible_moves {
-            children.push(Node::new(state, mov, node.clone()).unwrap());
+        let current = &self.nodes[node as usize];
+        let index = match pending {
+            Some(index) => index,
+            None if current.state.is_dealer_turn() => self.sample_chance_child(node, rng),
+            None => rng.random_range(
+                (current.first_child + current.expanded) as usize
+                    ..(current.first_child + current.child_count) as usize,
+            ),
+        };
+        if self.edges[index].node == UNEXPANDED {
+            self.expand_edge(node, index, rules)
+        } else {
+            index
         }
+    }
 
-        children[0].clone()
+    fn add_virtual_loss(&mut self, path: &[(NodeId, Option<usize>)]) {
+        for &(node, edge) in path {
+            self.nodes[node as usize].virtual_loss += 1.0;
+            if let Some(index) = edge {
+                self.edges[index].virtual_loss += 1.0;
+            }
+        }
     }
 
-    fn backpropagate(node: Arc<RwLock<Node>>, reward: f64) {
-        let mut maybe_node = Some(node.clone());
-        while let Some(current_node) = maybe_node.clone() {
-            let mut current_node_aq = current_node.write().unwrap();
-            current_node_aq.visits += 1.0;
-            current_node_aq.reward += reward;
-            maybe_node = current_node_aq.parent.clone()
+    /// Records the playout result along `path`, lifting the virtual loss placed during selection.
+    fn backpropagate(&mut self, path: &[(NodeId, Option<usize>)], reward: f64) {
+        for &(node, edge) in path {
+            let current = &mut self.nodes[node as usize];
+            current.visits += 1.0;
+            current.reward += reward;
+            current.reward_squared += reward * reward;
+            current.virtual_loss -= 1.0;
+            if let Some(index) = edge {
+                let edge = &mut self.edges[index];
+                edge.reward += reward;
+                edge.reward_squared += reward * reward;
+                edge.visits += 1.0;
+                edge.virtual_loss -= 1.0;
+            }
         }
     }
 
-    pub fn mcts(root: Arc<RwLock<Node>>, stop: Arc<AtomicBool>) {
-        let mut rng = rand::rng();
+    /// Runs the search until `stop` is set or the workers sharing `iterations` have claimed
+    /// `max_iterations` between them. Several workers can share one tree: the write lock is held
+    /// for selection, expansion and backpropagation, and released only for the playout, which is
+    /// at most four moves long. Workers sharing a tree therefore mostly wait on each other, with
+    /// virtual loss steering them apart only for the playouts that overlap. Given one worker and
+    /// an iteration budget, a seeded `rng` makes the search reproducible.
+    pub fn mcts(
+        tree: Arc<RwLock<Tree>>,
+        worker: Worker,
+        stop: Arc<AtomicBool>,
+        iterations: Arc<AtomicU64>,
+        max_iterations: u64,
+    ) {
+        let Worker {
+            rules,
+            shoe,
+            policy,
+            fpu,
+            max_nodes,
+            mut rng,
+            book,
+        } = worker;
+        let mut path = vec![];
 
-        while !stop.load(atomic::Ordering::Acquire) {
-            let mut node = root.clone();
+        // A claim past the budget leaves the counter alone, so it only counts iterations that ran.
+        let claim = || {
+            iterations
+                .fetch_update(atomic::Ordering::Relaxed, atomic::Ordering::Relaxed, |n| {
+                    (n < max_iterations).then_some(n + 1)
+                })
+                .is_ok()
+        };
+        while !stop.load(atomic::Ordering::Acquire) && claim() {
+            let state = {
+                let mut tree = tree.write().unwrap();
 
-            node = Node::select_node(node);
+                path.clear();
+                tree.select_node(&mut path, policy.as_ref(), fpu, &mut rng);
 
-            if !node.read().unwrap().state.is_terminal() {
-                node = Node::expand(node);
-            }
+                let (leaf, pending) = *path.last().unwrap();
+                if tree.nodes.len() >= max_nodes {
+                    path.last_mut().unwrap().1 = None;
+                } else if !tree.nodes[leaf as usize].state.is_terminal() {
+                    let index =
+                        tree.expand(leaf, pending, &rules, &shoe, book.as_deref(), &mut rng);
+                    path.last_mut().unwrap().1 = Some(index);
+                    path.push((tree.edges[index].node, None));
+                }
 
-            let reward = node.read().unwrap().state.playout(&mut rng) as f64 / 20.0;
+                tree.add_virtual_loss(&path);
+                let (leaf, _) = path.last().unwrap();
+                tree.nodes[*leaf as usize].state
+            };
 
-            Node::backpropagate(node, reward);
+            let reward = rules.normalise(state.playout(&rules, &shoe, &mut rng));
+
+            tree.write().unwrap().backpropagate(&path, reward);
         }
     }
 
-    pub fn get_best_moves(&self) -> Vec<(Move, f64)> {
-        self.children
+    pub fn root_visits(&self) -> f64 {
+        self.nodes[self.root as usize].visits
+    }
+
+    pub fn root_stats(&self) -> Vec<MoveStats> {
+        let mut values = HashMap::new();
+        self.edges[self.nodes[self.root as usize].expanded()]
             .iter()
-            .filter_map(|c| {
-                c.read()
-                    .unwrap()
-                    .mov
-                    .map(|mov| (mov, c.read().unwrap().visits / self.visits))
+            .map(|edge| MoveStats {
+                mov: edge.mov,
+                visits: edge.visits,
+                reward: edge.reward,
+                reward_squared: edge.reward_squared,
+                survival: edge.survival,
+                value: self.value(edge.node, &mut values),
             })
             .collect()
     }
 
-    pub fn find_child(node: Arc<RwLock<Node>>, mov: Move) -> Option<Arc<RwLock<Node>>> {
-        node.read()
-            .unwrap()
-            .children
+    /// Estimates the value of `node` by backing up the tree's statistics as the game is played:
+    /// the best tried move at player nodes and the weighted outcomes at dealer nodes. Means
+    /// averaged over every playout are dragged down by exploration and by random play below the
+    /// tree, so this approaches the exact value much sooner. Nodes with fewer than
+    /// `BACKUP_VISITS` visits, and outcomes not yet played out, fall back to the playout mean.
+    /// `values` caches shared nodes.
+    fn value(&self, node: NodeId, values: &mut HashMap<NodeId, f64>) -> f64 {
+        if let Some(value) = values.get(&node) {
+            return *value;
+        }
+        let current = &self.nodes[node as usize];
+        let mean = current.reward / current.visits;
+        let value = if current.visits < BACKUP_VISITS || current.expanded == 0 {
+            mean
+        } else if current.state.is_dealer_turn() {
+            let (total, weights) =
+                self.edges[current.children()]
+                    .iter()
+                    .fold((0.0, 0.0), |(total, weights), edge| {
+                        let value = match edge.node {
+                            child
+                                if child != UNEXPANDED
+                                    && self.nodes[child as usize].visits > 0.0 =>
+                            {
+                                self.value(child, values)
+                            }
+                            _ => mean,
+                        };
+                        let weight = edge.weight as f64;
+                        (total + value * weight, weights + weight)
+                    });
+            total / weights
+        } else {
+            self.edges[current.expanded()]
+                .iter()
+                .filter(|edge| edge.visits >= BACKUP_VISITS)
+                .map(|edge| self.value(edge.node, values))
+                .fold(None, |best: Option<f64>, value| {
+                    Some(best.map_or(value, |best| best.max(value)))
+                })
+                .unwrap_or(mean)
+        };
+        values.insert(node, value);
+        value
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Estimated bytes held by the arenas and the transposition table.
+    pub fn memory(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<Node>()
+            + self.edges.capacity() * mem::size_of::<Edge>()
+            + self.table.capacity() * (mem::size_of::<State>() + mem::size_of::<NodeId>() + 1)
+    }
+
+    /// Rebuilds the arenas with only the nodes reachable from the root, which becomes node 0.
+    /// Every id changes, so no worker may hold a path into the tree. The old arenas are returned
+    /// rather than dropped, as freeing a large tree takes a while.
+    pub fn compact(&mut self) -> Garbage {
+        let mut ids = vec![UNEXPANDED; self.nodes.len()];
+        let mut order = vec![self.root];
+        ids[self.root as usize] = 0;
+        let mut next = 0;
+        while next < order.len() {
+            for index in self.nodes[order[next] as usize].expanded() {
+                let child = self.edges[index].node;
+                if ids[child as usize] == UNEXPANDED {
+                    ids[child as usize] = order.len() as NodeId;
+                    order.push(child);
+                }
+            }
+            next += 1;
+        }
+
+        let mut nodes = Vec::with_capacity(order.len());
+        let mut edges = vec![];
+        let mut table = HashMap::with_capacity(order.len());
+        for old in order {
+            let node = &self.nodes[old as usize];
+            let first_child = edges.len() as u32;
+            edges.extend(self.edges[node.children()].iter().map(|edge| Edge {
+                node: match edge.node {
+                    UNEXPANDED => UNEXPANDED,
+                    child => ids[child as usize],
+                },
+                ..*edge
+            }));
+            table.insert(node.state, nodes.len() as NodeId);
+            nodes.push(Node {
+                first_child,
+                ..*node
+            });
+        }
+
+        let before = self.memory();
+        self.root = 0;
+        let nodes = mem::replace(&mut self.nodes, nodes);
+        let edges = mem::replace(&mut self.edges, edges);
+        let table = mem::replace(&mut self.table, table);
+        Garbage {
+            _nodes: nodes,
+            _edges: edges,
+            _table: table,
+            reclaimed: before.saturating_sub(self.memory()),
+        }
+    }
+
+    /// Re-roots the tree at the child reached by `mov`, given in the root's frame, expanding its
+    /// edge first if the search has not tried it yet. Dealer edges stand for every card with the
+    /// same outcome, so the child is found by the state `mov` leads to rather than by the move.
+    /// Returns the map from the old root's frame into the new root's.
+    pub fn advance(
+        &mut self,
+        mov: Move,
+        rules: &Rules,
+        shoe: &Shoe,
+        book: Option<&Book>,
+    ) -> Option<SuitMap> {
+        let state = self.nodes[self.root as usize].state;
+        // Outcomes are matched by bucket below, so a card the shoe cannot deal must be caught here.
+        if !state
+            .get_weighted_moves(rules, shoe)
             .iter()
-            .find(|c| c.read().unwrap().mov == Some(mov))
-            .cloned()
+            .any(|&(legal, _)| legal == mov)
+        {
+            return None;
+        }
+        let (child_state, map) = state.apply_move(mov, rules)?.canonical(&self.symmetries);
+        if self.nodes[self.root as usize].child_count == 0 {
+            self.reserve_children(self.root, rules, shoe, book);
+        }
+
+        let leads_to_child = |edge: &Edge| {
+            state
+                .apply_move(edge.mov, rules)
+                .is_some_and(|child| child.canonical(&self.symmetries).0 == child_state)
+        };
+        let root = &self.nodes[self.root as usize];
+        let index = root
+            .children()
+            .find(|&index| leads_to_child(&self.edges[index]))?;
+        let index = if self.edges[index].node == UNEXPANDED {
+            self.expand_edge(self.root, index, rules)
+        } else {
+            index
+        };
+
+        self.root = self.edges[index].node;
+        Some(map)
+    }
+}
+
+/// The arenas left behind by `Tree::compact`, so the caller can choose where to free them.
+pub struct Garbage {
+    _nodes: Vec<Node>,
+    _edges: Vec<Edge>,
+    _table: HashMap<State, NodeId>,
+    /// Estimated bytes the compaction freed.
+    pub reclaimed: usize,
+}
+
+/// One tree shared by every worker, or one tree per worker, all rooted at `state`. Returns the
+/// trees with the map from the real frame into their root's frame.
+pub fn new_trees(
+    parallelism: Parallelism,
+    threads: usize,
+    shoe: &Shoe,
+    state: State,
+) -> (Vec<Arc<RwLock<Tree>>>, SuitMap) {
+    let count = match parallelism {
+        Parallelism::Tree => 1,
+        Parallelism::Root => threads,
+    };
+    let mut frame = SuitMap::IDENTITY;
+    let trees = (0..count)
+        .map(|_| {
+            let (tree, map) = Tree::from_state(shoe, state);
+            frame = map;
+            Arc::new(RwLock::new(tree))
+        })
+        .collect();
+    (trees, frame)
+}
+
+/// Sums the root statistics of trees searched from the same state. Trees built from the same
+/// shoe canonicalise identically, so their root moves share a frame.
+pub fn merge_root_stats(trees: &[Arc<RwLock<Tree>>]) -> (f64, Vec<MoveStats>) {
+    let mut root_visits = 0.0;
+    let mut merged: Vec<MoveStats> = vec![];
+    for tree in trees {
+        let tree = tree.read().unwrap();
+        root_visits += tree.root_visits();
+        for stats in tree.root_stats() {
+            match merged.iter_mut().find(|m| m.mov == stats.mov) {
+                Some(m) => {
+                    m.value = (m.value * m.visits + stats.value * stats.visits)
+                        / (m.visits + stats.visits);
+                    m.visits += stats.visits;
+                    m.reward += stats.reward;
+                    m.reward_squared += stats.reward_squared;
+                    m.survival = m.survival.or(stats.survival);
+                }
+                None => merged.push(stats),
+            }
+        }
     }
+    (root_visits, merged)
 }
@@ -0,0 +1,171 @@
+use std::{f64::consts::SQRT_2, fmt, str::FromStr};
+
+use rand::RngCore;
+use rand_distr::{Beta, Distribution, Normal};
+
+/// What a selection policy knows about one child of a player node. Rewards are normalised into
+/// [0, 1], and pending virtual losses count as visits with no reward.
+#[derive(Debug, Clone, Copy)]
+pub struct ChildStats {
+    pub mean: f64,
+    pub variance: f64,
+    pub visits: f64,
+    /// Prior probability of the move, summing to one over the parent's children.
+    pub prior: f64,
+}
+
+/// Scores children of a player node; selection follows the highest score.
+pub trait SelectionPolicy: Send + Sync {
+    fn score(&self, child: &ChildStats, parent_visits: f64, rng: &mut dyn RngCore) -> f64;
+}
+
+/// UCB1 with exploration constant `c`.
+pub struct Ucb1 {
+    pub c: f64,
+}
+impl SelectionPolicy for Ucb1 {
+    fn score(&self, child: &ChildStats, parent_visits: f64, _: &mut dyn RngCore) -> f64 {
+        child.mean + self.c * (parent_visits.ln() / child.visits).sqrt()
+    }
+}
+
+/// UCB1-Tuned, which caps each child's exploration by an upper bound on its reward variance.
+pub struct Ucb1Tuned;
+impl SelectionPolicy for Ucb1Tuned {
+    fn score(&self, child: &ChildStats, parent_visits: f64, _: &mut dyn RngCore) -> f64 {
+        let log = parent_visits.ln() / child.visits;
+        let variance_bound = child.variance + (2.0 * log).sqrt();
+        child.mean + (log * variance_bound.min(0.25)).sqrt()
+    }
+}
+
+/// PUCT as in AlphaZero, spending exploration in proportion to each move's prior.
+pub struct Puct {
+    pub c: f64,
+}
+impl SelectionPolicy for Puct {
+    fn score(&self, child: &ChildStats, parent_visits: f64, _: &mut dyn RngCore) -> f64 {
+        child.mean + self.c * child.prior * parent_visits.sqrt() / (1.0 + child.visits)
+    }
+}
+
+/// Thompson sampling treating each playout as a Bernoulli trial with success chance equal to its
+/// reward, under a uniform Beta prior.
+pub struct ThompsonBernoulli;
+impl SelectionPolicy for ThompsonBernoulli {
+    fn score(&self, child: &ChildStats, _: f64, rng: &mut dyn RngCore) -> f64 {
+        if child.visits == 0.0 {
+            return Beta::new(1.0, 1.0).unwrap().sample(rng);
+        }
+        let wins = child.mean * child.visits;
+        Beta::new(1.0 + wins, 1.0 + child.visits - wins)
+            .unwrap()
+            .sample(rng)
+    }
+}
+
+/// Thompson sampling from a normal approximation to the posterior of the mean reward. The sample
+/// variance is padded by a prior of 1/4, the most a reward in [0, 1] can have, fading with visits,
+/// so a move whose playouts have all paid the same, like `Finish`, is still revisited.
+pub struct ThompsonGaussian;
+impl SelectionPolicy for ThompsonGaussian {
+    fn score(&self, child: &ChildStats, _: f64, rng: &mut dyn RngCore) -> f64 {
+        let variance = child.variance + 0.25 / (child.visits + 1.0);
+        let std_err = (variance / child.visits.max(1.0)).sqrt();
+        Normal::new(child.mean, std_err).unwrap().sample(rng)
+    }
+}
+
+/// First-play urgency: how children that have never been visited are scored, since the policies
+/// need at least one visit to estimate a mean.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Fpu {
+    /// Visit every unvisited child before any child is visited twice.
+    #[default]
+    First,
+    /// Score unvisited children as this normalised reward, so visited children that do better
+    /// are preferred.
+    Value(f64),
+}
+impl Fpu {
+    /// The score of an unvisited child.
+    pub fn score(&self) -> f64 {
+        match *self {
+            Fpu::First => f64::INFINITY,
+            Fpu::Value(value) => value,
+        }
+    }
+}
+impl FromStr for Fpu {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = "FPU must be first or a reward between 0 and 1";
+        if s.eq_ignore_ascii_case("first") {
+            return Ok(Fpu::First);
+        }
+        match s.parse::<f64>() {
+            Ok(value) if (0.0..=1.0).contains(&value) => Ok(Fpu::Value(value)),
+            _ => Err(err),
+        }
+    }
+}
+
+/// A selection policy picked on the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyKind {
+    Ucb1(f64),
+    Ucb1Tuned,
+    Puct(f64),
+    ThompsonBernoulli,
+    ThompsonGaussian,
+}
+impl PolicyKind {
+    pub fn build(&self) -> Box<dyn SelectionPolicy> {
+        match *self {
+            PolicyKind::Ucb1(c) => Box::new(Ucb1 { c }),
+            PolicyKind::Ucb1Tuned => Box::new(Ucb1Tuned),
+            PolicyKind::Puct(c) => Box::new(Puct { c }),
+            PolicyKind::ThompsonBernoulli => Box::new(ThompsonBernoulli),
+            PolicyKind::ThompsonGaussian => Box::new(ThompsonGaussian),
+        }
+    }
+}
+impl Default for PolicyKind {
+    fn default() -> Self {
+        PolicyKind::Ucb1(SQRT_2)
+    }
+}
+impl FromStr for PolicyKind {
+    type Err = &'static str;
+
+    /// Accepts `ucb1`, `ucb1-tuned`, `puct`, `thompson-bernoulli` or `thompson-gaussian`, with
+    /// an optional `:C` exploration constant for `ucb1` and `puct`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = "Policy must be ucb1[:C], ucb1-tuned, puct[:C], thompson-bernoulli or \
+                   thompson-gaussian";
+        let (name, c) = match s.split_once(':') {
+            Some((name, c)) => (name, Some(c.parse::<f64>().map_err(|_| err)?)),
+            None => (s, None),
+        };
+        match (name.to_ascii_lowercase().as_str(), c) {
+            ("ucb1", c) => Ok(PolicyKind::Ucb1(c.unwrap_or(SQRT_2))),
+            ("puct", c) => Ok(PolicyKind::Puct(c.unwrap_or(1.0))),
+            ("ucb1-tuned", None) => Ok(PolicyKind::Ucb1Tuned),
+            ("thompson-bernoulli", None) => Ok(PolicyKind::ThompsonBernoulli),
+            ("thompson-gaussian", None) => Ok(PolicyKind::ThompsonGaussian),
+            _ => Err(err),
+        }
+    }
+}
+impl fmt::Display for PolicyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyKind::Ucb1(c) => write!(f, "UCB1 (C = {:.2})", c),
+            PolicyKind::Ucb1Tuned => write!(f, "UCB1-Tuned"),
+            PolicyKind::Puct(c) => write!(f, "PUCT (C = {:.2})", c),
+            PolicyKind::ThompsonBernoulli => write!(f, "Bernoulli Thompson"),
+            PolicyKind::ThompsonGaussian => write!(f, "Gaussian Thompson"),
+        }
+    }
+}
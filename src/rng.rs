@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+use rand::{RngCore, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+/// Which generator drives the search and its playouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngKind {
+    Pcg,
+    Xoshiro,
+}
+impl FromStr for RngKind {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pcg" => Ok(RngKind::Pcg),
+            "xoshiro" => Ok(RngKind::Xoshiro),
+            _ => Err("RNG must be pcg or xoshiro"),
+        }
+    }
+}
+
+/// A seedable generator of either kind, so the search doesn't have to be generic over it.
+pub enum SearchRng {
+    Pcg(Pcg64Mcg),
+    Xoshiro(Xoshiro256PlusPlus),
+}
+impl SearchRng {
+    pub fn new(kind: RngKind, seed: u64) -> Self {
+        match kind {
+            RngKind::Pcg => SearchRng::Pcg(Pcg64Mcg::seed_from_u64(seed)),
+            RngKind::Xoshiro => SearchRng::Xoshiro(Xoshiro256PlusPlus::seed_from_u64(seed)),
+        }
+    }
+}
+impl RngCore for SearchRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            SearchRng::Pcg(rng) => rng.next_u32(),
+            SearchRng::Xoshiro(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            SearchRng::Pcg(rng) => rng.next_u64(),
+            SearchRng::Xoshiro(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        match self {
+            SearchRng::Pcg(rng) => rng.fill_bytes(dst),
+            SearchRng::Xoshiro(rng) => rng.fill_bytes(dst),
+        }
+    }
+}
+
+/// Derives an independent seed for the `index`th stream from `seed`, using the SplitMix64
+/// finaliser so that neighbouring indices give unrelated seeds.
+pub fn derive_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Multipliers paid when cashing out after each stage, or on winning the final stage. `same` is
+/// paid for a correct `Move::Same` guess, which ends the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayoutTable {
+    pub stage1: u32,
+    pub stage2: u32,
+    pub stage3: u32,
+    pub stage4: u32,
+    pub same: u32,
+}
+impl PayoutTable {
+    pub fn max(&self) -> u32 {
+        [
+            self.stage1,
+            self.stage2,
+            self.stage3,
+            self.stage4,
+            self.same,
+        ]
+        .into_iter()
+        .max()
+        .unwrap()
+    }
+}
+impl Default for PayoutTable {
+    fn default() -> Self {
+        PayoutTable {
+            stage1: 2,
+            stage2: 3,
+            stage3: 4,
+            stage4: 20,
+            same: 10,
+        }
+    }
+}
+impl FromStr for PayoutTable {
+    type Err = &'static str;
+
+    /// Parses four comma separated multipliers, e.g. `2,3,4,20`, optionally followed by the
+    /// payout for `Move::Same`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let payouts = s
+            .split(',')
+            .map(|x| x.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| "Payouts must be whole numbers")?;
+        let table = match payouts[..] {
+            [stage1, stage2, stage3, stage4] => PayoutTable {
+                stage1,
+                stage2,
+                stage3,
+                stage4,
+                ..Default::default()
+            },
+            [stage1, stage2, stage3, stage4, same] => PayoutTable {
+                stage1,
+                stage2,
+                stage3,
+                stage4,
+                same,
+            },
+            _ => return Err("Expected four or five payouts"),
+        };
+        // Rewards are scaled by the largest payout, so at least one must be positive.
+        if table.max() == 0 {
+            return Err("At least one payout must be positive");
+        }
+        Ok(table)
+    }
+}
+
+/// What happens when the dealer's card has the same value as one we are comparing against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TiePolicy {
+    /// Every guess loses on a tie.
+    #[default]
+    Lose,
+    /// The game ends paying the multiplier held before the guess.
+    Push,
+    /// Outside wins on a tie with either boundary card, Higher and Lower still lose.
+    OutsideWins,
+    /// Every guess loses on a tie, but `Move::Same` can be played to bet on one.
+    Same,
+}
+impl FromStr for TiePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lose" => Ok(TiePolicy::Lose),
+            "push" => Ok(TiePolicy::Push),
+            "outside" => Ok(TiePolicy::OutsideWins),
+            "same" => Ok(TiePolicy::Same),
+            _ => Err("Tie policy must be one of lose, push, outside or same"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rules {
+    pub payouts: PayoutTable,
+    pub ties: TiePolicy,
+    /// Number of decks shuffled together into the shoe.
+    pub decks: u8,
+}
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            payouts: PayoutTable::default(),
+            ties: TiePolicy::default(),
+            decks: 1,
+        }
+    }
+}
+impl Rules {
+    /// Scales a multiplier into `[0, 1]` for the search.
+    pub fn normalise(&self, multiplier: u32) -> f64 {
+        multiplier as f64 / self.payouts.max() as f64
+    }
+
+    /// Scales a normalised reward back into a multiplier.
+    pub fn denormalise(&self, reward: f64) -> f64 {
+        reward * self.payouts.max() as f64
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{self, AtomicBool, AtomicU64},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    card::Shoe,
+    game::{Move, State},
+    node::{self, MoveStats, Parallelism, Tree, Worker},
+    policy::{Fpu, PolicyKind},
+    rng::{self, RngKind, SearchRng},
+    rules::Rules,
+};
+
+/// When a headless search should stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Budget {
+    /// Stop after this many iterations across all workers.
+    Iterations(u64),
+    /// Stop after this much wall-clock time.
+    Time(Duration),
+    /// Stop once the best move's 95% interval no longer overlaps any other move's, or after the
+    /// given time if that never happens.
+    Separated(Duration),
+}
+impl FromStr for Budget {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = "Budget must be iterations:N, time:SECS or separated:SECS";
+        let (kind, value) = s.split_once(':').ok_or(err)?;
+        match kind.to_ascii_lowercase().as_str() {
+            "iterations" => Ok(Budget::Iterations(value.parse().map_err(|_| err)?)),
+            "time" => Ok(Budget::Time(parse_seconds(value).ok_or(err)?)),
+            "separated" => Ok(Budget::Separated(parse_seconds(value).ok_or(err)?)),
+            _ => Err(err),
+        }
+    }
+}
+
+fn parse_seconds(s: &str) -> Option<Duration> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+/// How a search spreads its work, and how its random numbers are drawn.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchConfig {
+    pub parallelism: Parallelism,
+    pub threads: usize,
+    pub policy: PolicyKind,
+    pub fpu: Fpu,
+    /// Stops expanding each tree once it holds this many nodes.
+    pub max_nodes: usize,
+    pub rng: RngKind,
+    /// Seeds every worker's generator, or a random seed when `None`.
+    pub seed: Option<u64>,
+}
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            parallelism: Parallelism::Tree,
+            threads: 1,
+            policy: PolicyKind::default(),
+            fpu: Fpu::default(),
+            max_nodes: usize::MAX,
+            rng: RngKind::Xoshiro,
+            seed: None,
+        }
+    }
+}
+impl SearchConfig {
+    /// Worker `index` of a search seeded with `seed`.
+    pub fn worker(&self, rules: Rules, shoe: &Shoe, seed: u64, index: usize) -> Worker {
+        Worker {
+            rules,
+            shoe: shoe.clone(),
+            policy: self.policy.build(),
+            fpu: self.fpu,
+            max_nodes: self.max_nodes,
+            rng: SearchRng::new(self.rng, rng::derive_seed(seed, index as u64)),
+            book: None,
+        }
+    }
+}
+
+/// The outcome of a headless search, with moves in the real frame of the searched state.
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub moves: Vec<MoveStats>,
+    pub iterations: u64,
+    pub elapsed: Duration,
+    /// The seed the workers were derived from, to repeat the search.
+    pub seed: u64,
+}
+impl SearchResult {
+    /// The most visited move, or `None` if the state has no moves.
+    pub fn best(&self) -> Option<&MoveStats> {
+        self.moves
+            .iter()
+            .max_by(|x, y| x.visits.total_cmp(&y.visits))
+    }
+}
+impl Display for SearchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} iterations in {:.2}s with seed {}",
+            self.iterations,
+            self.elapsed.as_secs_f64(),
+            self.seed
+        )?;
+        for stats in &self.moves {
+            writeln!(
+                f,
+                "{:<20} {:>10} visits, mean reward {:.4} ± {:.4} (95% {:.4} to {:.4})",
+                stats.mov.to_string(),
+                stats.visits,
+                stats.mean(),
+                stats.std_err(),
+                stats.interval().0,
+                stats.interval().1
+            )?;
+            if let Some(survival) = stats.survival {
+                writeln!(
+                    f,
+                    "{:<20} {:.1}% survive the next card",
+                    "",
+                    survival * 100.0
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Searches `state` until `budget` runs out, without a TUI.
+pub fn search(
+    state: State,
+    rules: Rules,
+    shoe: &Shoe,
+    budget: Budget,
+    config: SearchConfig,
+) -> SearchResult {
+    let start = Instant::now();
+    let seed = config.seed.unwrap_or_else(rand::random);
+    let (trees, frame) = node::new_trees(config.parallelism, config.threads, shoe, state);
+    let stop = Arc::new(AtomicBool::new(false));
+    let iterations = Arc::new(AtomicU64::new(0));
+    let max_iterations = match budget {
+        Budget::Iterations(n) => n,
+        _ => u64::MAX,
+    };
+
+    let workers = (0..config.threads)
+        .map(|i| {
+            let tree = trees[i % trees.len()].clone();
+            let worker = config.worker(rules, shoe, seed, i);
+            let stop = stop.clone();
+            let iterations = iterations.clone();
+            thread::spawn(move || {
+                Tree::mcts(tree, worker, stop, iterations, max_iterations);
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Root moves are in the trees' frame, and moves leading to the same canonical child share its
+    // statistics, so separation is judged between distinct children.
+    let symmetries = shoe.symmetries();
+    let root = state.map_suits(&frame);
+    let child = |mov: Move| {
+        root.apply_move(mov, &rules)
+            .map(|child| child.canonical(&symmetries).0)
+    };
+
+    loop {
+        let done = match budget {
+            Budget::Iterations(_) => workers.iter().all(|worker| worker.is_finished()),
+            Budget::Time(limit) => start.elapsed() >= limit,
+            Budget::Separated(limit) => {
+                start.elapsed() >= limit || separated(&node::merge_root_stats(&trees).1, child)
+            }
+        };
+        if done {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    stop.store(true, atomic::Ordering::Release);
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    let to_real = frame.inverse();
+    let moves = node::merge_root_stats(&trees)
+        .1
+        .into_iter()
+        .map(|stats| MoveStats {
+            mov: stats.mov.map_suits(&to_real),
+            ..stats
+        })
+        .collect();
+    SearchResult {
+        moves,
+        iterations: iterations.load(atomic::Ordering::Relaxed),
+        elapsed: start.elapsed(),
+        seed,
+    }
+}
+
+/// Whether the most visited move's 95% interval lies above that of every move leading to a
+/// different `child`. Moves sharing the best move's child are the same choice.
+fn separated(moves: &[MoveStats], child: impl Fn(Move) -> Option<State>) -> bool {
+    let Some(best) = moves.iter().max_by(|x, y| x.visits.total_cmp(&y.visits)) else {
+        return false;
+    };
+    let (lower, _) = best.interval();
+    let best_child = child(best.mov);
+    moves
+        .iter()
+        .filter(|stats| child(stats.mov) != best_child)
+        .all(|stats| stats.interval().1 < lower)
+}
+
+/// Prints a headless search from the start of the game, for scripts and benchmarks.
+pub fn print(rules: Rules, shoe: &Shoe, budget: Budget, config: SearchConfig) {
+    let result = search(State::Start, rules, shoe, budget, config);
+    print!("{}", result);
+    if let Some(best) = result.best() {
+        println!("Best move: {}", best.mov);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_search_is_reproducible() {
+        let rules = Rules::default();
+        let shoe = Shoe::new(rules.decks);
+        let config = SearchConfig {
+            seed: Some(7),
+            ..SearchConfig::default()
+        };
+        let run = || {
+            search(
+                State::Start,
+                rules,
+                &shoe,
+                Budget::Iterations(20_000),
+                config,
+            )
+        };
+        let (first, second) = (run(), run());
+        assert_eq!(first.iterations, 20_000);
+        assert_eq!(first.moves, second.moves);
+    }
+}
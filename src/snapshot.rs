@@ -0,0 +1,71 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    card::{Card, Shoe, SuitMap},
+    game::State,
+    node::{Parallelism, Tree},
+    rules::Rules,
+};
+
+/// A saved session: the search trees along with everything they were built from, so analysis
+/// resumes where it stopped.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot<T = Tree> {
+    pub rules: Rules,
+    /// The shoe before this round's cards were dealt.
+    pub shoe: Shoe,
+    pub round_cards: Vec<Card>,
+    /// The real state of the round.
+    pub state: State,
+    /// Relabels suits from the real game into the trees' root frame.
+    pub frame: SuitMap,
+    /// How the trees were searched: one shared tree, or one tree per worker.
+    pub parallelism: Parallelism,
+    pub trees: Vec<T>,
+}
+impl<T: Serialize> Snapshot<T> {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write(path, self)
+    }
+}
+impl Snapshot {
+    /// Reads a snapshot written by `save`, in the format its extension names.
+    pub fn load(path: &Path) -> io::Result<Snapshot> {
+        let mut snapshot: Snapshot = read(path)?;
+        for tree in &mut snapshot.trees {
+            tree.restore();
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Writes `value` as JSON if `path` ends in `.json`, or as compact binary otherwise.
+pub fn write<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    if is_json(path) {
+        serde_json::to_writer(writer, value).map_err(io::Error::other)
+    } else {
+        bincode::serialize_into(writer, value).map_err(io::Error::other)
+    }
+}
+
+/// Reads a value written by `write`, in the format the extension of `path` names.
+pub fn read<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let reader = BufReader::new(File::open(path)?);
+    if is_json(path) {
+        serde_json::from_reader(reader).map_err(io::Error::other)
+    } else {
+        bincode::deserialize_from(reader).map_err(io::Error::other)
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
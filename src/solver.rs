@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use crate::{
+    card::{Shoe, SuitMap},
+    game::{Move, State},
+    rules::Rules,
+};
+
+/// Exact expectimax over the full game tree. Player states take the best move, dealer states
+/// average over every card left in the shoe. Values are cached by canonical state, so positions
+/// that only differ by suit are solved once and re-rooting is cheap.
+pub struct Solver {
+    rules: Rules,
+    shoe: Shoe,
+    symmetries: Vec<SuitMap>,
+    cache: HashMap<State, f64>,
+}
+impl Solver {
+    pub fn new(rules: Rules, shoe: Shoe) -> Self {
+        Solver {
+            rules,
+            symmetries: shoe.symmetries(),
+            shoe,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn expected_value(&mut self, state: State) -> f64 {
+        if let State::Finished(multiplier) = state {
+            return multiplier as f64;
+        }
+        let (state, _) = state.canonical(&self.symmetries);
+        if let Some(value) = self.cache.get(&state) {
+            return *value;
+        }
+
+        let values = state
+            .get_weighted_moves(&self.rules, &self.shoe)
+            .into_iter()
+            .map(|(mov, weight)| {
+                let value = self.expected_value(state.apply_move(mov, &self.rules).unwrap());
+                (value, weight as f64)
+            });
+
+        let value = if state.is_dealer_turn() {
+            let (total, weights) = values.fold((0.0, 0.0), |(total, weights), (x, weight)| {
+                (total + x * weight, weights + weight)
+            });
+            total / weights
+        } else {
+            values.map(|(x, _)| x).fold(f64::NEG_INFINITY, f64::max)
+        };
+
+        self.cache.insert(state, value);
+        value
+    }
+
+    pub fn move_values(&mut self, state: State) -> Vec<(Move, f64)> {
+        state
+            .get_valid_moves(&self.rules, &self.shoe)
+            .into_iter()
+            .map(|mov| {
+                (
+                    mov,
+                    self.expected_value(state.apply_move(mov, &self.rules).unwrap()),
+                )
+            })
+            .collect()
+    }
+}